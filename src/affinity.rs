@@ -0,0 +1,172 @@
+use crate::{config::AffinityConfig, server::ServerId};
+use axum::http::HeaderMap;
+use endpoints::chat::{ChatCompletionRequest, ChatCompletionRequestMessage};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Part of a chat request that identifies its session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AffinityKey {
+    /// The `user` field of the request
+    #[default]
+    User,
+    /// The header configured in `header`
+    Header,
+    /// A hash of the messages of the conversation up to and including its first user message,
+    /// which do not change from one turn to the next
+    Prefix,
+}
+
+/// Returns the session key of a chat request, if it has one. The key includes the requested
+/// model so that a session using several models is pinned once per model.
+pub(crate) fn session_key(
+    config: &AffinityConfig,
+    headers: &HeaderMap,
+    request: &ChatCompletionRequest,
+) -> Option<String> {
+    let session = match config.key {
+        AffinityKey::User => request.user.clone()?,
+        AffinityKey::Header => headers.get(&config.header)?.to_str().ok()?.to_string(),
+        AffinityKey::Prefix => {
+            let first_user = request
+                .messages
+                .iter()
+                .position(|message| matches!(message, ChatCompletionRequestMessage::User(_)))?;
+            let prefix = &request.messages[..=first_user];
+
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            serde_json::to_string(prefix).ok()?.hash(&mut hasher);
+            format!("{:016x}", hasher.finish())
+        }
+    };
+
+    Some(format!(
+        "{}/{}",
+        request.model.as_deref().unwrap_or_default(),
+        session
+    ))
+}
+
+#[derive(Debug)]
+struct Pin {
+    server_id: ServerId,
+    last_used: Instant,
+}
+
+/// Bounded mapping of sessions to the servers they were last routed to. A mapping expires if
+/// the session sends no request within the TTL.
+#[derive(Debug, Default)]
+pub(crate) struct AffinityTable {
+    pins: Mutex<HashMap<String, Pin>>,
+}
+impl AffinityTable {
+    /// Returns the server the session is pinned to
+    pub(crate) fn get(&self, key: &str, ttl: Duration) -> Option<ServerId> {
+        let mut pins = self.pins.lock().unwrap();
+        match pins.get(key) {
+            Some(pin) if pin.last_used.elapsed() < ttl => Some(pin.server_id.clone()),
+            Some(_) => {
+                pins.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Pins the session to a server. If the table is full, the expired mappings are dropped,
+    /// then the least recently used one.
+    pub(crate) fn pin(&self, key: String, server_id: ServerId, ttl: Duration, max_entries: usize) {
+        let mut pins = self.pins.lock().unwrap();
+        if !pins.contains_key(&key) && pins.len() >= max_entries {
+            pins.retain(|_, pin| pin.last_used.elapsed() < ttl);
+
+            if pins.len() >= max_entries {
+                let oldest = pins
+                    .iter()
+                    .min_by_key(|(_, pin)| pin.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    pins.remove(&oldest);
+                }
+            }
+        }
+
+        pins.insert(
+            key,
+            Pin {
+                server_id,
+                last_used: Instant::now(),
+            },
+        );
+    }
+}
+
+#[test]
+fn test_prefix_session_key() {
+    use endpoints::chat::ChatCompletionUserMessageContent;
+
+    let config = AffinityConfig {
+        key: AffinityKey::Prefix,
+        ..Default::default()
+    };
+    let user_message = |text: &str| {
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(text.to_string()),
+            None,
+        )
+    };
+
+    let mut request = ChatCompletionRequest {
+        model: Some("Llama-3.2-3B".to_string()),
+        messages: vec![
+            ChatCompletionRequestMessage::new_system_message("You are a helpful assistant.", None),
+            user_message("What is the capital of France?"),
+        ],
+        ..Default::default()
+    };
+    let first_turn = session_key(&config, &HeaderMap::new(), &request).unwrap();
+
+    // the second turn of the conversation maps to the session of the first one
+    request
+        .messages
+        .push(ChatCompletionRequestMessage::new_assistant_message(
+            Some("Paris.".to_string()),
+            None,
+            None,
+        ));
+    request.messages.push(user_message("And of Germany?"));
+    let second_turn = session_key(&config, &HeaderMap::new(), &request).unwrap();
+    assert_eq!(first_turn, second_turn);
+
+    // another conversation does not
+    request.messages[1] = user_message("What is the capital of Italy?");
+    let other = session_key(&config, &HeaderMap::new(), &request).unwrap();
+    assert_ne!(first_turn, other);
+}
+
+#[test]
+fn test_affinity_table() {
+    let table = AffinityTable::default();
+    let ttl = Duration::from_secs(60);
+
+    table.pin("a".to_string(), "server-1".to_string(), ttl, 2);
+    std::thread::sleep(Duration::from_millis(2));
+    table.pin("b".to_string(), "server-2".to_string(), ttl, 2);
+    assert_eq!(table.get("a", ttl), Some("server-1".to_string()));
+
+    // the least recently pinned session is evicted from a full table
+    table.pin("c".to_string(), "server-3".to_string(), ttl, 2);
+    assert_eq!(table.get("a", ttl), None);
+    assert_eq!(table.get("b", ttl), Some("server-2".to_string()));
+    assert_eq!(table.get("c", ttl), Some("server-3".to_string()));
+
+    // an expired mapping is dropped
+    assert_eq!(table.get("b", Duration::ZERO), None);
+    assert_eq!(table.get("b", ttl), None);
+}
@@ -0,0 +1,154 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Arm of the traffic split of a model alias
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CanaryArm {
+    /// The `model` of the alias and its fallbacks
+    Stable,
+    /// The `canary` model of the alias
+    Canary,
+}
+
+/// Picks the arm of a request for an alias sending `percent` percent of its traffic to the
+/// canary. The requests of a user always get the same arm, so that a conversation does not flip
+/// between the models. Requests without a user are split at random.
+pub(crate) fn pick_arm(alias: &str, user: Option<&str>, percent: u8) -> CanaryArm {
+    let bucket = match user {
+        Some(user) => user_bucket(alias, user),
+        None => rand::thread_rng().gen_range(0..100),
+    };
+
+    match bucket < percent as u64 {
+        true => CanaryArm::Canary,
+        false => CanaryArm::Stable,
+    }
+}
+
+// Bucket of a user in the split of an alias. The 64-bit FNV-1a hash is fixed, unlike the hasher
+// of the standard library, so users keep their arm across releases of Rust and LlamaEdge-Nexus.
+fn user_bucket(alias: &str, user: &str) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    // the separator keeps ("ab", "c") and ("a", "bc") apart
+    let bytes = alias.bytes().chain([0]).chain(user.bytes());
+    let hash = bytes.fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    });
+
+    hash % 100
+}
+
+/// Requests routed to an arm and how many of them failed
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub(crate) struct ArmCounters {
+    pub(crate) requests: u64,
+    pub(crate) errors: u64,
+}
+
+/// Counters of the arms of the traffic splits, by alias
+#[derive(Debug, Default)]
+pub(crate) struct CanaryStats {
+    arms: Mutex<HashMap<(String, CanaryArm), ArmCounters>>,
+}
+impl CanaryStats {
+    /// Counts a request routed to an arm of the split of an alias
+    pub(crate) fn record(&self, alias: &str, arm: CanaryArm, failed: bool) {
+        let mut arms = self.arms.lock().unwrap();
+        let counters = arms.entry((alias.to_string(), arm)).or_default();
+        counters.requests += 1;
+        if failed {
+            counters.errors += 1;
+        }
+    }
+
+    /// Returns the counters of an arm of the split of an alias
+    pub(crate) fn get(&self, alias: &str, arm: CanaryArm) -> ArmCounters {
+        let arms = self.arms.lock().unwrap();
+        arms.get(&(alias.to_string(), arm))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Resets the counters of both arms of the split of an alias
+    pub(crate) fn reset(&self, alias: &str) {
+        self.arms
+            .lock()
+            .unwrap()
+            .retain(|(name, _), _| name != alias);
+    }
+}
+
+/// An arm of a traffic split reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ArmStatus {
+    pub(crate) model: String,
+    #[serde(flatten)]
+    pub(crate) counters: ArmCounters,
+}
+
+/// Traffic split of a model alias reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CanaryStatus {
+    pub(crate) name: String,
+    pub(crate) canary_percent: u8,
+    pub(crate) stable: ArmStatus,
+    pub(crate) canary: ArmStatus,
+}
+
+/// Request body of the admin endpoint that adjusts the traffic split of a model alias
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct CanaryUpdate {
+    /// Name of the alias
+    pub(crate) name: String,
+    /// Percentage of the traffic of the alias routed to the canary model
+    pub(crate) canary_percent: u8,
+    /// Replaces the canary model, which resets the counters of the split
+    #[serde(default)]
+    pub(crate) canary: Option<String>,
+}
+
+#[test]
+fn test_pick_arm() {
+    // the arm of a user does not change between requests
+    let arm = pick_arm("gpt-4o-mini", Some("alice"), 50);
+    for _ in 0..10 {
+        assert_eq!(pick_arm("gpt-4o-mini", Some("alice"), 50), arm);
+    }
+
+    assert_eq!(pick_arm("gpt-4o-mini", Some("alice"), 0), CanaryArm::Stable);
+    assert_eq!(pick_arm("gpt-4o-mini", None, 0), CanaryArm::Stable);
+    assert_eq!(
+        pick_arm("gpt-4o-mini", Some("alice"), 100),
+        CanaryArm::Canary
+    );
+
+    // the users are split by the percentage
+    let canary_users = (0..1000)
+        .map(|i| format!("user-{}", i))
+        .filter(|user| pick_arm("gpt-4o-mini", Some(user), 20) == CanaryArm::Canary)
+        .count();
+    assert!((120..280).contains(&canary_users), "{}", canary_users);
+}
+
+#[test]
+fn test_user_bucket() {
+    // the buckets must not change, or users would switch arms after an upgrade
+    assert_eq!(user_bucket("gpt-4o-mini", "alice"), 36);
+    assert_eq!(user_bucket("gpt-4o-mini", "bob"), 19);
+    assert_eq!(user_bucket("gpt-4o-mini", "carol"), 93);
+    assert_eq!(user_bucket("llama-3", "alice"), 58);
+
+    assert_eq!(
+        pick_arm("gpt-4o-mini", Some("alice"), 37),
+        CanaryArm::Canary
+    );
+    assert_eq!(
+        pick_arm("gpt-4o-mini", Some("alice"), 36),
+        CanaryArm::Stable
+    );
+    assert_eq!(pick_arm("llama-3", Some("alice"), 36), CanaryArm::Stable);
+}
@@ -0,0 +1,325 @@
+use crate::config::CircuitBreakerConfig;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// State of the circuit breaker of a downstream server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BreakerState {
+    /// Requests flow normally
+    #[default]
+    Closed,
+    /// The server is failing and receives no requests until the cooldown is over
+    Open,
+    /// The cooldown is over and a single trial request is let through
+    HalfOpen,
+}
+impl std::fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakerState::Closed => write!(f, "closed"),
+            BreakerState::Open => write!(f, "open"),
+            BreakerState::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+/// Snapshot of a circuit breaker reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BreakerStatus {
+    pub(crate) state: BreakerState,
+    /// Share of failed requests in the recent window
+    pub(crate) error_rate: f64,
+    /// Number of requests in the recent window
+    pub(crate) recent_requests: usize,
+}
+
+#[derive(Debug, Default)]
+struct BreakerInner {
+    state: BreakerState,
+    /// Outcomes of the most recent requests, `true` for a failure
+    window: VecDeque<bool>,
+    /// When an open breaker lets the next trial request through
+    open_until: Option<Instant>,
+    /// Id of the trial request of a half-open breaker that is still running
+    trial: Option<u64>,
+    /// Id of the next trial request
+    next_trial: u64,
+}
+
+/// Passive circuit breaker driven by the outcomes of real requests to a server
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreaker {
+    inner: Mutex<BreakerInner>,
+}
+impl CircuitBreaker {
+    /// Returns whether the server may be picked for a new request
+    pub(crate) fn is_available(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => inner.open_until.is_some_and(|t| Instant::now() >= t),
+            BreakerState::HalfOpen => inner.trial.is_none(),
+        }
+    }
+
+    /// Notes that a request is dispatched to the server. An open breaker whose cooldown is over
+    /// lets the request through as its trial request, and rejects any other request until the
+    /// trial ends.
+    pub(crate) fn on_dispatch(self: &Arc<Self>) -> Dispatch {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            BreakerState::Closed => return Dispatch::Pass,
+            BreakerState::Open if inner.open_until.is_none_or(|t| Instant::now() < t) => {
+                return Dispatch::Reject
+            }
+            BreakerState::HalfOpen if inner.trial.is_some() => return Dispatch::Reject,
+            _ => {}
+        }
+
+        inner.state = BreakerState::HalfOpen;
+        let id = inner.next_trial;
+        inner.next_trial += 1;
+        inner.trial = Some(id);
+
+        Dispatch::Trial(Trial {
+            breaker: self.clone(),
+            id,
+        })
+    }
+
+    // Lets another trial through if the given trial ended without an outcome
+    fn release_trial(&self, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == BreakerState::HalfOpen && inner.trial == Some(id) {
+            inner.trial = None;
+        }
+    }
+
+    /// Records the outcome of a request, given the id of the trial if it was a trial request.
+    /// Returns the new state if it changed.
+    pub(crate) fn record(
+        &self,
+        failed: bool,
+        trial: Option<u64>,
+        config: &CircuitBreakerConfig,
+    ) -> Option<BreakerState> {
+        if !config.enable {
+            return None;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            BreakerState::Closed => {
+                inner.window.push_back(failed);
+                while inner.window.len() > config.window.max(1) {
+                    inner.window.pop_front();
+                }
+
+                let failures = inner.window.iter().filter(|failed| **failed).count();
+                let requests = inner.window.len();
+                if requests >= config.min_requests
+                    && failures as f64 >= config.error_rate_threshold * requests as f64
+                {
+                    Self::trip(&mut inner, config);
+                    return Some(BreakerState::Open);
+                }
+
+                None
+            }
+            // only the outcome of the running trial decides, not a late result of a request
+            // dispatched before the breaker opened
+            BreakerState::HalfOpen if trial.is_none() || trial != inner.trial => None,
+            BreakerState::HalfOpen => {
+                inner.trial = None;
+                match failed {
+                    true => {
+                        Self::trip(&mut inner, config);
+                        Some(BreakerState::Open)
+                    }
+                    false => {
+                        inner.state = BreakerState::Closed;
+                        inner.window.clear();
+                        inner.open_until = None;
+                        Some(BreakerState::Closed)
+                    }
+                }
+            }
+            // late results of requests dispatched before the breaker opened
+            BreakerState::Open => None,
+        }
+    }
+
+    pub(crate) fn status(&self) -> BreakerStatus {
+        let inner = self.inner.lock().unwrap();
+        let failures = inner.window.iter().filter(|failed| **failed).count();
+        BreakerStatus {
+            state: inner.state,
+            error_rate: match inner.window.len() {
+                0 => 0.0,
+                requests => failures as f64 / requests as f64,
+            },
+            recent_requests: inner.window.len(),
+        }
+    }
+
+    fn trip(inner: &mut BreakerInner, config: &CircuitBreakerConfig) {
+        inner.state = BreakerState::Open;
+        inner.trial = None;
+        inner.open_until = Some(Instant::now() + Duration::from_secs(config.cooldown));
+    }
+}
+
+/// Decision of a circuit breaker on a request dispatched to its server
+#[derive(Debug)]
+pub(crate) enum Dispatch {
+    /// The breaker is closed and the request flows normally
+    Pass,
+    /// The request is the trial request of the breaker
+    Trial(Trial),
+    /// The breaker lets no request through, e.g. because its trial request is still running
+    Reject,
+}
+
+/// Trial request let through by a half-open breaker. If the trial is dropped without an
+/// outcome, e.g. because the client went away, the breaker lets another trial through.
+#[derive(Debug)]
+pub(crate) struct Trial {
+    breaker: Arc<CircuitBreaker>,
+    id: u64,
+}
+impl Trial {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+}
+impl Drop for Trial {
+    fn drop(&mut self) {
+        self.breaker.release_trial(self.id);
+    }
+}
+
+#[test]
+fn test_circuit_breaker_transitions() {
+    let config = CircuitBreakerConfig {
+        window: 4,
+        min_requests: 4,
+        error_rate_threshold: 0.5,
+        cooldown: 0,
+        ..Default::default()
+    };
+    let breaker = Arc::new(CircuitBreaker::default());
+
+    assert_eq!(breaker.record(true, None, &config), None);
+    assert_eq!(breaker.record(false, None, &config), None);
+    assert_eq!(breaker.record(false, None, &config), None);
+    assert_eq!(
+        breaker.record(true, None, &config),
+        Some(BreakerState::Open)
+    );
+    assert_eq!(breaker.status().state, BreakerState::Open);
+
+    // the cooldown is over, a single trial request is let through
+    assert!(breaker.is_available());
+    let Dispatch::Trial(trial) = breaker.on_dispatch() else {
+        panic!("no trial dispatched")
+    };
+    assert_eq!(breaker.status().state, BreakerState::HalfOpen);
+    assert!(!breaker.is_available());
+
+    // the trial fails and the breaker opens again
+    assert_eq!(
+        breaker.record(true, Some(trial.id()), &config),
+        Some(BreakerState::Open)
+    );
+    drop(trial);
+
+    // the next trial succeeds and the breaker closes
+    let Dispatch::Trial(trial) = breaker.on_dispatch() else {
+        panic!("no trial dispatched")
+    };
+    assert_eq!(
+        breaker.record(false, Some(trial.id()), &config),
+        Some(BreakerState::Closed)
+    );
+    drop(trial);
+    assert!(breaker.is_available());
+    assert!(matches!(breaker.on_dispatch(), Dispatch::Pass));
+    assert_eq!(breaker.status().recent_requests, 0);
+}
+
+#[test]
+fn test_circuit_breaker_cancelled_trial() {
+    let config = CircuitBreakerConfig {
+        window: 1,
+        min_requests: 1,
+        cooldown: 0,
+        ..Default::default()
+    };
+    let breaker = Arc::new(CircuitBreaker::default());
+    assert_eq!(
+        breaker.record(true, None, &config),
+        Some(BreakerState::Open)
+    );
+
+    // a trial dropped without an outcome lets the next trial through
+    let Dispatch::Trial(trial) = breaker.on_dispatch() else {
+        panic!("no trial dispatched")
+    };
+    assert!(!breaker.is_available());
+    drop(trial);
+    assert_eq!(breaker.status().state, BreakerState::HalfOpen);
+    assert!(breaker.is_available());
+
+    // a stale trial does not release the trial that replaced it
+    let Dispatch::Trial(first) = breaker.on_dispatch() else {
+        panic!("no trial dispatched")
+    };
+    assert_eq!(
+        breaker.record(true, Some(first.id()), &config),
+        Some(BreakerState::Open)
+    );
+    let Dispatch::Trial(second) = breaker.on_dispatch() else {
+        panic!("no trial dispatched")
+    };
+    drop(first);
+    assert!(!breaker.is_available());
+    drop(second);
+    assert!(breaker.is_available());
+}
+
+#[test]
+fn test_circuit_breaker_concurrent_dispatch() {
+    let config = CircuitBreakerConfig {
+        window: 1,
+        min_requests: 1,
+        cooldown: 0,
+        ..Default::default()
+    };
+    let breaker = Arc::new(CircuitBreaker::default());
+    assert_eq!(
+        breaker.record(true, None, &config),
+        Some(BreakerState::Open)
+    );
+
+    // two requests dispatched during half-open: only the first one is the trial
+    let Dispatch::Trial(trial) = breaker.on_dispatch() else {
+        panic!("no trial dispatched")
+    };
+    assert!(matches!(breaker.on_dispatch(), Dispatch::Reject));
+
+    // a late result of a request dispatched before the breaker opened does not decide
+    assert_eq!(breaker.record(false, None, &config), None);
+    assert_eq!(breaker.status().state, BreakerState::HalfOpen);
+    assert!(matches!(breaker.on_dispatch(), Dispatch::Reject));
+
+    // the outcome of the trial does
+    assert_eq!(
+        breaker.record(false, Some(trial.id()), &config),
+        Some(BreakerState::Closed)
+    );
+}
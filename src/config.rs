@@ -1,11 +1,40 @@
+use crate::{
+    affinity::AffinityKey,
+    routing::RoutingStrategy,
+    server::{Server, ServerKind},
+};
 use chat_prompts::MergeRagContextPolicy;
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub server: ServerConfig,
     pub rag: RagConfig,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    #[serde(default)]
+    pub refresh: RefreshConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    #[serde(default)]
+    pub affinity: AffinityConfig,
+    /// Public model names mapped to the models of the downstream servers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<ModelAlias>,
+    /// Downstream servers registered on startup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<Server>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub server_info_push_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -16,7 +45,41 @@ impl Config {
         let config = config::Config::builder()
             .add_source(config::File::with_name(path.as_ref().to_str().unwrap()))
             .build()?;
-        Ok(config.try_deserialize::<Self>()?)
+        let config = config.try_deserialize::<Self>()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Check the settings that cannot be checked while deserializing
+    fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for alias in self.models.iter() {
+            if !names.insert(alias.name.as_str()) {
+                return Err(format!("Duplicate model alias `{}`", alias.name));
+            }
+            if alias.canary_percent > 100 {
+                return Err(format!(
+                    "The canary percentage of the model alias `{}` must be between 0 and 100",
+                    alias.name
+                ));
+            }
+        }
+
+        // an alias would hide the model of the same name from the requests
+        for alias in self.models.iter() {
+            if let Some(model) = alias
+                .chain()
+                .chain(alias.canary.iter())
+                .find(|model| names.contains(model.as_str()))
+            {
+                return Err(format!(
+                    "The model alias `{}` routes to `{}`, which is the name of a model alias",
+                    alias.name, model
+                ));
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -27,6 +90,7 @@ impl Default for Config {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 8080,
+                state_file: None,
             },
             rag: RagConfig {
                 enable: false,
@@ -41,6 +105,15 @@ impl Default for Config {
                 },
                 kw_search: KwSearchConfig::default(),
             },
+            routing: RoutingConfig::default(),
+            health_check: HealthCheckConfig::default(),
+            refresh: RefreshConfig::default(),
+            retry: RetryConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            queue: QueueConfig::default(),
+            affinity: AffinityConfig::default(),
+            models: Vec::new(),
+            servers: Vec::new(),
             server_info_push_url: None,
             server_health_push_url: None,
         }
@@ -51,6 +124,9 @@ impl Default for Config {
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// File the registered servers are persisted to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -108,9 +184,399 @@ pub struct VectorDbConfig {
     pub score_threshold: f32,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoutingConfig {
+    /// Model used for chat requests that do not specify one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_chat_model: Option<String>,
+    /// Model used for embeddings requests that do not specify one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_embedding_model: Option<String>,
+    /// Routing strategy per server kind. Kinds not listed use `least-connections`.
+    #[serde(default)]
+    pub strategy: HashMap<ServerKind, RoutingStrategy>,
+    /// Route chat requests only to the servers whose context window holds them
+    #[serde(default = "default_true")]
+    pub check_context_length: bool,
+}
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            default_chat_model: None,
+            default_embedding_model: None,
+            strategy: HashMap::new(),
+            check_context_length: true,
+        }
+    }
+}
+impl RoutingConfig {
+    /// Returns the routing strategy configured for the given server kind
+    pub fn strategy_for(&self, kind: ServerKind) -> RoutingStrategy {
+        self.strategy.get(&kind).copied().unwrap_or_default()
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Whether to probe the registered servers in the background
+    pub enable: bool,
+    /// Seconds between two rounds of probes
+    pub interval: u64,
+    /// Seconds to wait for a probe response
+    pub timeout: u64,
+    /// Path requested on each server
+    pub path: String,
+    /// Consecutive failed probes after which a server is marked unhealthy
+    pub unhealthy_threshold: u32,
+    /// Consecutive successful probes after which an unhealthy server is marked healthy again
+    pub healthy_threshold: u32,
+}
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            interval: 10,
+            timeout: 5,
+            path: "/v1/info".to_string(),
+            unhealthy_threshold: 3,
+            healthy_threshold: 2,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RefreshConfig {
+    /// Whether to refresh the server info and the model lists in the background
+    pub enable: bool,
+    /// Seconds between two refreshes
+    pub interval: u64,
+}
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            interval: 300,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Maximum number of retries of a single request. `0` disables retries.
+    pub max_retries: u32,
+    /// Milliseconds to wait before the first retry. Doubled on every further retry.
+    pub backoff_ms: u64,
+    /// Upper bound of the wait between two retries in milliseconds
+    pub max_backoff_ms: u64,
+    /// Downstream response statuses that are retried on another server
+    pub retry_on_status: Vec<u16>,
+}
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            backoff_ms: 100,
+            max_backoff_ms: 2000,
+            retry_on_status: vec![502, 503],
+        }
+    }
+}
+impl RetryConfig {
+    /// Returns the wait before the given retry, starting from `1`
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        let factor = 1u64
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        let backoff_ms = self
+            .backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms);
+        std::time::Duration::from_millis(backoff_ms)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// Maximum number of requests per server kind waiting for a saturated server
+    pub max_size: usize,
+    /// Seconds a request waits for a free slot before it is rejected with `429`
+    pub timeout: u64,
+    /// Seconds sent in the `Retry-After` header of a rejected request
+    pub retry_after: u64,
+}
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 100,
+            timeout: 30,
+            retry_after: 1,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AffinityConfig {
+    /// Whether to route the chat requests of a session to the server of its previous requests
+    pub enable: bool,
+    /// Part of a request that identifies its session
+    pub key: AffinityKey,
+    /// Header identifying the session if `key` is `header`
+    pub header: String,
+    /// Seconds after which the mapping of an idle session expires
+    pub ttl: u64,
+    /// Maximum number of sessions mapped to servers
+    pub max_entries: usize,
+}
+impl Default for AffinityConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            key: AffinityKey::User,
+            header: "x-session-id".to_string(),
+            ttl: 600,
+            max_entries: 10000,
+        }
+    }
+}
+
+/// Models of the downstream servers a public model name stands for
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ModelAlias {
+    /// Public model name, e.g. `gpt-4o-mini`
+    pub name: String,
+    /// Model the requests for the alias are routed to
+    pub model: String,
+    /// Models tried in order if no healthy server serves `model`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<String>,
+    /// Model receiving `canary_percent` percent of the traffic of the alias, e.g. a new fine-tune
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<String>,
+    /// Percentage of the traffic of the alias routed to the canary model
+    #[serde(default)]
+    pub canary_percent: u8,
+}
+impl Config {
+    /// Returns the alias with the given public model name
+    pub(crate) fn model_alias(&self, name: &str) -> Option<&ModelAlias> {
+        self.models.iter().find(|alias| alias.name == name)
+    }
+
+    /// Returns the alias with the given public model name for updating it
+    pub(crate) fn model_alias_mut(&mut self, name: &str) -> Option<&mut ModelAlias> {
+        self.models.iter_mut().find(|alias| alias.name == name)
+    }
+}
+impl ModelAlias {
+    /// Returns the model followed by its fallbacks
+    pub(crate) fn chain(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.model).chain(self.fallbacks.iter())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Whether to stop routing to servers failing on real traffic
+    pub enable: bool,
+    /// Number of recent requests the error rate of a server is computed over
+    pub window: usize,
+    /// Minimum number of requests in the window before the breaker may open
+    pub min_requests: usize,
+    /// Error rate in the window at which the breaker opens
+    pub error_rate_threshold: f64,
+    /// Responses slower than this many milliseconds count as failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_request_ms: Option<u64>,
+    /// Seconds an open breaker waits before letting a trial request through
+    pub cooldown: u64,
+}
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            window: 20,
+            min_requests: 10,
+            error_rate_threshold: 0.5,
+            slow_request_ms: None,
+            cooldown: 30,
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct KwSearchConfig {
     pub enable: bool,
     pub url: String,
     pub index_name: String,
 }
+
+#[test]
+fn test_deserialize_routing_config() {
+    let toml = r#"
+        default_chat_model = "Llama-3.2-3B"
+
+        [strategy]
+        chat = "least-latency"
+        embeddings = "round-robin"
+    "#;
+    let routing = config::Config::builder()
+        .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize::<RoutingConfig>()
+        .unwrap();
+
+    assert_eq!(routing.default_chat_model.as_deref(), Some("Llama-3.2-3B"));
+    assert_eq!(routing.default_embedding_model, None);
+    assert_eq!(
+        routing.strategy_for(ServerKind::chat),
+        RoutingStrategy::LeastLatency
+    );
+    assert_eq!(
+        routing.strategy_for(ServerKind::embeddings),
+        RoutingStrategy::RoundRobin
+    );
+    assert_eq!(
+        routing.strategy_for(ServerKind::tts),
+        RoutingStrategy::LeastConnections
+    );
+}
+
+#[test]
+fn test_retry_backoff() {
+    let retry = RetryConfig {
+        backoff_ms: 100,
+        max_backoff_ms: 500,
+        ..Default::default()
+    };
+
+    assert_eq!(retry.backoff(1).as_millis(), 100);
+    assert_eq!(retry.backoff(2).as_millis(), 200);
+    assert_eq!(retry.backoff(3).as_millis(), 400);
+    assert_eq!(retry.backoff(4).as_millis(), 500);
+    assert_eq!(retry.backoff(100).as_millis(), 500);
+}
+
+#[test]
+fn test_deserialize_model_aliases() {
+    #[derive(Deserialize)]
+    struct Models {
+        models: Vec<ModelAlias>,
+    }
+
+    let toml = r#"
+        [[models]]
+        name  = "gpt-4o-mini"
+        model = "Llama-3.2-3B"
+
+        [[models]]
+        name      = "gpt-4.1"
+        model     = "Qwen2.5-72B"
+        fallbacks = ["Qwen2.5-7B", "Llama-3.2-3B"]
+    "#;
+    let models = config::Config::builder()
+        .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize::<Models>()
+        .unwrap()
+        .models;
+
+    assert_eq!(models[0].chain().collect::<Vec<_>>(), vec!["Llama-3.2-3B"]);
+    assert_eq!(
+        models[1].chain().collect::<Vec<_>>(),
+        vec!["Qwen2.5-72B", "Qwen2.5-7B", "Llama-3.2-3B"]
+    );
+}
+
+#[test]
+fn test_validate_model_aliases() {
+    let alias = |name: &str, model: &str, canary_percent| ModelAlias {
+        name: name.to_string(),
+        model: model.to_string(),
+        fallbacks: Vec::new(),
+        canary: None,
+        canary_percent,
+    };
+    let config = |models| Config {
+        models,
+        ..Default::default()
+    };
+
+    assert!(config(vec![alias("gpt-4o-mini", "Llama-3.2-3B", 10)])
+        .validate()
+        .is_ok());
+    assert!(config(vec![alias("gpt-4o-mini", "Llama-3.2-3B", 150)])
+        .validate()
+        .is_err());
+    assert!(config(vec![
+        alias("gpt-4o-mini", "Llama-3.2-3B", 0),
+        alias("gpt-4o-mini", "Qwen2.5-7B", 0),
+    ])
+    .validate()
+    .is_err());
+
+    // the alias `Llama-3.2-3B` would hide the model of the same name
+    assert!(config(vec![
+        alias("gpt-4o-mini", "Llama-3.2-3B", 0),
+        alias("Llama-3.2-3B", "Qwen2.5-7B", 0),
+    ])
+    .validate()
+    .is_err());
+}
+
+#[test]
+fn test_deserialize_static_servers() {
+    let toml = r#"
+        [server]
+        host = "0.0.0.0"
+        port = 9068
+
+        [rag]
+        prompt = ""
+        rag_policy = "system-message"
+        context_window = 1
+
+        [rag.vector_db]
+        url = "http://localhost:6333"
+        collection_name = ["default"]
+        limit = 1
+        score_threshold = 0.5
+
+        [rag.kw_search]
+        enable = false
+        url = ""
+        index_name = ""
+
+        [[servers]]
+        url = "http://localhost:10010"
+        kind = "chat"
+
+        [[servers]]
+        url = "http://localhost:10011"
+        kind = "embeddings"
+        weight = 2
+    "#;
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize::<Config>()
+        .unwrap();
+
+    assert_eq!(config.servers.len(), 2);
+    assert_eq!(config.servers[0].url, "http://localhost:10010");
+    assert_eq!(config.servers[0].kind, ServerKind::chat);
+    assert_eq!(config.servers[1].weight, 2);
+    assert!(config.servers[1].id.starts_with("embeddings-server-"));
+}
@@ -0,0 +1,139 @@
+use endpoints::chat::ChatCompletionRequest;
+use once_cell::sync::Lazy;
+use tiktoken_rs::CoreBPE;
+
+/// Tokens added by the chat template around every message
+const TOKENS_PER_MESSAGE: usize = 4;
+/// Tokens priming the reply of the assistant
+const TOKENS_PER_REPLY: usize = 3;
+
+// The `cl100k_base` tokenizer. Encoding only borrows it, so concurrent requests do not wait on
+// each other, unlike with the locked singleton of `tiktoken_rs`.
+static CL100K_BASE: Lazy<CoreBPE> =
+    Lazy::new(|| tiktoken_rs::cl100k_base().expect("Failed to load the cl100k_base tokenizer"));
+
+/// Estimates the number of tokens a chat request needs in the context window of a server: the
+/// tokens of its messages, counted with the `cl100k_base` tokenizer, plus the tokens it asks
+/// to be generated with `max_completion_tokens`, or the deprecated `max_tokens`.
+pub(crate) fn estimate_request_tokens(request: &ChatCompletionRequest) -> u64 {
+    estimate_prompt_tokens(request) + completion_tokens(request)
+}
+
+/// Returns a bound the estimate of `estimate_request_tokens` never exceeds, computed without
+/// the tokenizer: every token of `cl100k_base` covers at least one byte of the text.
+pub(crate) fn request_tokens_upper_bound(request: &ChatCompletionRequest) -> u64 {
+    count_prompt_tokens(request, str::len) + completion_tokens(request)
+}
+
+/// Estimates the number of tokens of the messages of a chat request
+pub(crate) fn estimate_prompt_tokens(request: &ChatCompletionRequest) -> u64 {
+    count_prompt_tokens(request, |text| CL100K_BASE.encode_ordinary(text).len())
+}
+
+// Count the tokens of the messages of a chat request, counting the tokens of each text with the
+// given function
+fn count_prompt_tokens(request: &ChatCompletionRequest, count: impl Fn(&str) -> usize) -> u64 {
+    let messages = match serde_json::to_value(&request.messages) {
+        Ok(serde_json::Value::Array(messages)) => messages,
+        _ => return 0,
+    };
+
+    let mut tokens = TOKENS_PER_REPLY;
+    for message in messages.iter() {
+        let mut texts = Vec::new();
+        collect_texts(message, &mut texts);
+
+        tokens += TOKENS_PER_MESSAGE;
+        tokens += texts.iter().map(|text| count(text)).sum::<usize>();
+    }
+
+    tokens as u64
+}
+
+// Tokens a chat request asks to be generated with `max_completion_tokens`, or the deprecated
+// `max_tokens`
+fn completion_tokens(request: &ChatCompletionRequest) -> u64 {
+    request
+        .max_completion_tokens
+        .filter(|max_tokens| *max_tokens > 0)
+        .map(|max_tokens| max_tokens as u64)
+        .or(request.max_tokens.filter(|max_tokens| *max_tokens > 0))
+        .unwrap_or_default()
+}
+
+// Collect the text of a message: its content, the text parts of a multi-part content and the
+// arguments of tool calls
+fn collect_texts<'a>(value: &'a serde_json::Value, texts: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter() {
+                match (key.as_str(), value) {
+                    (
+                        "content" | "text" | "arguments" | "name",
+                        serde_json::Value::String(text),
+                    ) => texts.push(text),
+                    (_, value) => collect_texts(value, texts),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values.iter() {
+                collect_texts(value, texts);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn test_collect_texts() {
+    let message = serde_json::json!({
+        "role": "user",
+        "content": [
+            { "type": "text", "text": "What is in this image?" },
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+        ]
+    });
+
+    let mut texts = Vec::new();
+    collect_texts(&message, &mut texts);
+    assert_eq!(texts, vec!["What is in this image?"]);
+}
+
+#[test]
+fn test_estimate_completion_tokens() {
+    let mut request = ChatCompletionRequest {
+        max_tokens: Some(100),
+        ..Default::default()
+    };
+    assert_eq!(
+        estimate_request_tokens(&request),
+        TOKENS_PER_REPLY as u64 + 100
+    );
+
+    request.max_completion_tokens = Some(50);
+    assert_eq!(
+        estimate_request_tokens(&request),
+        TOKENS_PER_REPLY as u64 + 50
+    );
+}
+
+#[test]
+fn test_request_tokens_upper_bound() {
+    use endpoints::chat::{ChatCompletionRequestMessage, ChatCompletionUserMessageContent};
+
+    let request = ChatCompletionRequest {
+        messages: vec![ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text(
+                "The quick brown fox jumps over the lazy dog".to_string(),
+            ),
+            None,
+        )],
+        max_tokens: Some(100),
+        ..Default::default()
+    };
+
+    let estimate = estimate_request_tokens(&request);
+    let upper_bound = request_tokens_upper_bound(&request);
+    assert!(estimate < upper_bound, "{} < {}", estimate, upper_bound);
+}
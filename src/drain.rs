@@ -0,0 +1,61 @@
+use crate::{dual_info, dual_warn, registry, server::Server, AppState};
+use serde::Deserialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Request body of the admin endpoint that drains a server
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct DrainRequest {
+    /// Unregister the server once its in-flight requests are done
+    #[serde(default)]
+    pub(crate) unregister: bool,
+    /// Unregister the server after this many seconds even if requests are still in flight
+    #[serde(default)]
+    pub(crate) timeout_seconds: Option<u64>,
+}
+
+/// Waits until a draining server has no in-flight requests left or the timeout passes, then
+/// unregisters it. Gives up if the server is undrained in the meantime.
+pub(crate) async fn unregister_when_drained(
+    state: Arc<AppState>,
+    server: Server,
+    timeout: Option<Duration>,
+) {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    let started = Instant::now();
+    loop {
+        if !server.is_draining() {
+            dual_info!("Server {} was undrained, it stays registered", server.id);
+            return;
+        }
+
+        if server.in_flight() == 0 {
+            dual_info!("Server {} is drained", server.id);
+            break;
+        }
+
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            dual_warn!(
+                "Drain timeout of server {} passed with {} request(s) in flight",
+                server.id,
+                server.in_flight()
+            );
+            break;
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    match state.unregister_downstream_server(&server.id).await {
+        Ok(()) => {
+            dual_info!("Unregistered drained server {}", server.id);
+
+            // persist the registry
+            let _ = registry::save(&state).await;
+        }
+        Err(e) => dual_warn!("Failed to unregister drained server {}: {}", server.id, e),
+    }
+}
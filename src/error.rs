@@ -1,5 +1,9 @@
 use crate::dual_error;
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use hyper::{Body, Response};
 use thiserror::Error;
 
@@ -91,20 +95,64 @@ pub enum ServerError {
     /// Generic error returned while performing an operation
     #[error("{0}")]
     Operation(String),
+    #[error("{0}")]
+    NotFoundModel(String),
+    #[error("Server {0} not found")]
+    NotFoundServerId(String),
+    #[error("{0}")]
+    DuplicateServer(String),
     #[error("Invalid server kind: {0}")]
     InvalidServerKind(String),
     #[error("Bad request: {0}")]
     BadRequest(String),
     #[error("Failed to load config: {0}")]
     FailedToLoadConfig(String),
+    /// Every server that could serve the request is busy
+    #[error("{message}")]
+    TooManyRequests { message: String, retry_after: u64 },
+    /// The request does not fit into the context window of any server
+    #[error("{0}")]
+    ContextLengthExceeded(String),
 }
 impl IntoResponse for ServerError {
     fn into_response(self) -> axum::response::Response {
+        // respond like the OpenAI API does to the errors clients handle programmatically
         let (status, err_response) = match &self {
+            ServerError::TooManyRequests {
+                message,
+                retry_after,
+            } => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(openai_error(
+                        message,
+                        "requests",
+                        None,
+                        "rate_limit_exceeded",
+                    )),
+                )
+                    .into_response();
+            }
+            ServerError::ContextLengthExceeded(message) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(openai_error(
+                        message,
+                        "invalid_request_error",
+                        Some("messages"),
+                        "context_length_exceeded",
+                    )),
+                )
+                    .into_response();
+            }
             ServerError::SocketAddr(e) => (StatusCode::BAD_REQUEST, e.to_string()),
             ServerError::ArgumentError(e) => (StatusCode::BAD_REQUEST, e.to_string()),
             ServerError::Operation(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             ServerError::NotFoundServer(e) => (StatusCode::NOT_FOUND, e.to_string()),
+            ServerError::NotFoundModel(e) => (StatusCode::NOT_FOUND, e.to_string()),
+            ServerError::NotFoundServerId(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            ServerError::DuplicateServer(e) => (StatusCode::CONFLICT, e.to_string()),
             ServerError::InvalidServerKind(e) => (StatusCode::BAD_REQUEST, e.to_string()),
             ServerError::BadRequest(e) => (StatusCode::BAD_REQUEST, e.to_string()),
             ServerError::FailedToLoadConfig(e) => (StatusCode::BAD_REQUEST, e.to_string()),
@@ -114,4 +162,16 @@ impl IntoResponse for ServerError {
     }
 }
 
+// Build an error body in the format of the OpenAI API
+fn openai_error(message: &str, ty: &str, param: Option<&str>, code: &str) -> serde_json::Value {
+    serde_json::json!({
+        "error": {
+            "message": message,
+            "type": ty,
+            "param": param,
+            "code": code,
+        }
+    })
+}
+
 pub type ServerResult<T> = std::result::Result<T, ServerError>;
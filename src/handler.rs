@@ -1,22 +1,40 @@
 use crate::{
+    affinity,
+    canary::{self, CanaryArm, CanaryUpdate},
+    context,
+    drain::{self, DrainRequest},
     dual_debug, dual_error, dual_info, dual_warn,
     error::{ServerError, ServerResult},
     info::ApiServer,
-    rag,
-    server::{RoutingPolicy, Server, ServerIdToRemove, ServerKind},
+    rag, refresh,
+    registry::{self, ImportRequest},
+    routing::RoutingStrategyUpdate,
+    server::{
+        validate_labels, LabelSelector, ListOptions, RegisterOptions, RoutingPolicy, Server,
+        ServerFilter, ServerFlavor, ServerId, ServerIdToRemove, ServerKind, ServerUpdate,
+        TargetServer,
+    },
     AppState,
 };
 use axum::{
     body::Body,
-    extract::{Json, Multipart, State},
+    extract::{Json, Multipart, Path, Query, State},
     http::{HeaderMap, Request, Response, StatusCode},
 };
 use endpoints::{
     chat::ChatCompletionRequest,
     embeddings::{EmbeddingRequest, EmbeddingsResponse},
-    models::ListModelsResponse,
+    models::{ListModelsResponse, Model},
 };
-use std::sync::Arc;
+use futures_util::StreamExt;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Header restricting a request to the servers whose labels match a label selector
+pub(crate) const SELECTOR_HEADER: &str = "x-nexus-selector";
 
 pub(crate) async fn chat_handler(
     State(state): State<Arc<AppState>>,
@@ -33,7 +51,7 @@ pub(crate) async fn chat_handler(
 pub async fn chat(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(request): Json<ChatCompletionRequest>,
+    Json(mut request): Json<ChatCompletionRequest>,
 ) -> ServerResult<Response<Body>> {
     let request_id = headers
         .get("x-request-id")
@@ -43,72 +61,124 @@ pub async fn chat(
 
     dual_info!("Received a new chat request - request_id: {}", request_id);
 
-    // get the chat server
-    let chat_server_base_url = {
-        let servers = state.server_group.read().await;
-        let chat_servers = match servers.get(&ServerKind::chat) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No chat server available";
-                error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
+    // restrict the candidates to the chat servers serving the requested model
+    let route = chat_route(&state, &headers, &mut request, &request_id).await?;
 
-        match chat_servers.next().await {
-            Ok(url) => url,
-            Err(e) => {
-                let err_msg = format!("Failed to get the chat server: {}", e);
-                error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
+    forward_chat(state, headers, request, route, request_id).await
+}
 
-    let chat_service_url = format!("{}v1/chat/completions", chat_server_base_url);
-    dual_info!(
-        "Forward the chat request to {} - request_id: {}",
-        chat_service_url,
-        request_id
-    );
+/// Resolves the model of a chat request, falling back to the default chat model, to the route
+/// of the request: the model an alias stands for and the chat servers serving it
+pub(crate) async fn chat_route(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: &mut ChatCompletionRequest,
+    request_id: &str,
+) -> ServerResult<ModelRoute> {
+    // fall back to the default chat model if the request does not specify one
+    if request.model.is_none() {
+        request.model = state.config.read().await.routing.default_chat_model.clone();
+    }
+
+    let selector = label_selector(headers, request_id)?;
+    model_filter(
+        state,
+        ServerKind::chat,
+        request.model.as_deref(),
+        request.user.as_deref(),
+        selector,
+        request_id,
+    )
+    .await
+}
+
+/// Forwards a chat request to one of the chat servers of its route
+pub(crate) async fn forward_chat(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    mut request: ChatCompletionRequest,
+    route: ModelRoute,
+    request_id: String,
+) -> ServerResult<Response<Body>> {
+    // route the requests of a session to the server of its previous requests, if it can serve them
+    let affinity = state.config.read().await.affinity.clone();
+    let session_key = match affinity.enable {
+        true => affinity::session_key(&affinity, &headers, &request),
+        false => None,
+    };
+    let pinned_server = session_key
+        .as_ref()
+        .and_then(|key| state.affinity.get(key, Duration::from_secs(affinity.ttl)));
+    let filter = route.filter.prefer(pinned_server.clone());
+
+    // forward the model an alias resolves to, which the servers report in their responses
+    request.model = route.model;
+
+    // restrict the candidates to the chat servers whose context window holds the request
+    let filter = match state.config.read().await.routing.check_context_length {
+        true => context_filter(&state, filter, &request, &request_id).await?,
+        false => filter,
+    };
 
     let stream = request.stream;
 
-    // Create a request client that can be cancelled
-    let ds_response = reqwest::Client::new()
-        .post(chat_service_url)
-        .header("content-type", "application/json")
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| {
-            let err_msg = format!(
-                "Failed to forward the request to the downstream server: {}",
-                e
-            );
-            error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
-            ServerError::Operation(err_msg)
-        })?;
+    // forward the request, failing over to another chat server if necessary
+    let result = send_with_retry(
+        &state,
+        ServerKind::chat,
+        &filter,
+        "v1/chat/completions",
+        &request_id,
+        |url| {
+            reqwest::Client::new()
+                .post(url)
+                .header("content-type", "application/json")
+                .json(&request)
+        },
+    )
+    .await;
+    record_split(&state, &route.split, &result);
+    let (chat_server, ds_response) = result?;
 
     let status = ds_response.status();
 
-    // Handle response body reading with cancellation
-    let bytes = ds_response.bytes().await.map_err(|e| {
-        let err_msg = format!("Failed to get the full response as bytes: {}", e);
-        error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
-        ServerError::Operation(err_msg)
-    })?;
+    // pin the session to the server that served it
+    if let (Some(key), true) = (session_key, status.is_success()) {
+        if pinned_server.as_ref() != Some(&chat_server.id) {
+            dual_info!(
+                "Pinned the session to server {} - request_id: {}",
+                chat_server.id,
+                request_id
+            );
+        }
+        state.affinity.pin(
+            key,
+            chat_server.id.clone(),
+            Duration::from_secs(affinity.ttl),
+            affinity.max_entries,
+        );
+    }
 
     match stream {
         Some(true) => {
+            // forward the downstream event stream chunk by chunk instead of buffering it.
+            // The connection guard moves into the stream so that the in-flight slot is
+            // released once the stream ends or the client goes away.
+            let guard = chat_server.guard;
+            let body = Body::wrap_stream(ds_response.bytes_stream().map(move |chunk| {
+                let _guard = &guard;
+                chunk
+            }));
+
             match Response::builder()
                 .status(status)
                 .header("Content-Type", "text/event-stream")
-                .body(Body::from(bytes))
+                .header("Cache-Control", "no-cache")
+                .body(body)
             {
                 Ok(response) => {
                     dual_info!(
-                        "Chat request completed successfully - request_id: {}",
+                        "Chat request is streaming the response - request_id: {}",
                         request_id
                     );
                     Ok(response)
@@ -121,6 +191,13 @@ pub async fn chat(
             }
         }
         Some(false) | None => {
+            // Handle response body reading with cancellation
+            let bytes = ds_response.bytes().await.map_err(|e| {
+                let err_msg = format!("Failed to get the full response as bytes: {}", e);
+                error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
             match Response::builder()
                 .status(status)
                 .header("Content-Type", "application/json")
@@ -146,7 +223,7 @@ pub async fn chat(
 pub async fn embeddings_handler(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(request): Json<EmbeddingRequest>,
+    Json(mut request): Json<EmbeddingRequest>,
 ) -> ServerResult<Response<Body>> {
     // Get request ID from headers
     let request_id = headers
@@ -160,31 +237,30 @@ pub async fn embeddings_handler(
         request_id
     );
 
-    // get the embeddings server
-    let servers = state.server_group.read().await;
-    let embeddings_servers = match servers.get(&ServerKind::embeddings) {
-        Some(servers) => servers,
-        None => {
-            let err_msg = "No embeddings server available";
-            error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg.to_string()));
-        }
-    };
+    // fall back to the default embedding model if the request does not specify one
+    if request.model.is_none() {
+        request.model = state
+            .config
+            .read()
+            .await
+            .routing
+            .default_embedding_model
+            .clone();
+    }
 
-    let embeddings_server_base_url = match embeddings_servers.next().await {
-        Ok(url) => url,
-        Err(e) => {
-            let err_msg = format!("Failed to get the embeddings server: {}", e);
-            error!(target: "stdout", "{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg));
-        }
-    };
-    let embeddings_service_url = format!("{}v1/embeddings", embeddings_server_base_url);
-    dual_info!(
-        "Forward the embeddings request to {} - request_id: {}",
-        embeddings_service_url,
-        request_id
-    );
+    // restrict the candidates to the embeddings servers serving the requested model
+    let selector = label_selector(&headers, &request_id)?;
+    let route = model_filter(
+        &state,
+        ServerKind::embeddings,
+        request.model.as_deref(),
+        request.user.as_deref(),
+        selector,
+        &request_id,
+    )
+    .await?;
+    let filter = route.filter;
+    request.model = route.model;
 
     // parse the content-type header
     let content_type = headers
@@ -202,21 +278,23 @@ pub async fn embeddings_handler(
         request_id
     );
 
-    // Create request client
-    let ds_response = reqwest::Client::new()
-        .post(embeddings_service_url)
-        .header("Content-Type", content_type)
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| {
-            let err_msg = format!(
-                "Failed to forward the request to the downstream server: {}",
-                e
-            );
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            ServerError::Operation(err_msg)
-        })?;
+    // forward the request, failing over to another embeddings server if necessary
+    let result = send_with_retry(
+        &state,
+        ServerKind::embeddings,
+        &filter,
+        "v1/embeddings",
+        &request_id,
+        |url| {
+            reqwest::Client::new()
+                .post(url)
+                .header("Content-Type", content_type.as_str())
+                .json(&request)
+        },
+    )
+    .await;
+    record_split(&state, &route.split, &result);
+    let (_embeddings_server, ds_response) = result?;
 
     let status = ds_response.status();
 
@@ -264,57 +342,34 @@ pub(crate) async fn audio_transcriptions_handler(
         request_id
     );
 
-    // get the transcribe server
-    let transcribe_server_base_url = {
-        let servers = state.server_group.read().await;
-        let transcribe_servers = match servers.get(&ServerKind::transcribe) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No transcribe server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match transcribe_servers.next().await {
-            Ok(url) => url,
-            Err(e) => {
-                let err_msg = format!("Failed to get the transcribe server: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
-
-    let transcription_service_url =
-        format!("{}v1/audio/transcriptions", transcribe_server_base_url);
-    dual_info!(
-        "Forward the audio transcription request to {} - request_id: {}",
-        transcription_service_url,
-        request_id
-    );
-
-    // Create request client
-    let mut request_builder = reqwest::Client::new().post(transcription_service_url);
-    for (name, value) in req.headers().iter() {
-        request_builder = request_builder.header(name, value);
-    }
-
     // convert the request body into bytes
-    let body_bytes = hyper::body::to_bytes(req.into_body()).await.map_err(|e| {
+    let (parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await.map_err(|e| {
         let err_msg = format!("Failed to convert the request body into bytes: {}", e);
         dual_error!("{} - request_id: {}", err_msg, request_id);
         ServerError::Operation(err_msg)
     })?;
 
-    let ds_response = request_builder.body(body_bytes).send().await.map_err(|e| {
-        let err_msg = format!(
-            "Failed to forward the request to the downstream server: {}",
-            e
-        );
-        dual_error!("{} - request_id: {}", err_msg, request_id);
-        ServerError::Operation(err_msg)
-    })?;
+    // restrict the candidates to the transcribe servers matching the label selector
+    let filter =
+        ServerFilter::default().with_selector(label_selector(&parts.headers, &request_id)?);
+
+    // forward the request, failing over to another transcribe server if necessary
+    let (_transcribe_server, ds_response) = send_with_retry(
+        &state,
+        ServerKind::transcribe,
+        &filter,
+        "v1/audio/transcriptions",
+        &request_id,
+        |url| {
+            let mut request_builder = reqwest::Client::new().post(url);
+            for (name, value) in parts.headers.iter() {
+                request_builder = request_builder.header(name, value);
+            }
+            request_builder.body(body_bytes.clone())
+        },
+    )
+    .await?;
 
     let status = ds_response.status();
 
@@ -362,56 +417,34 @@ pub(crate) async fn audio_translations_handler(
         request_id
     );
 
-    // get the transcribe server
-    let translate_server_base_url = {
-        let servers = state.server_group.read().await;
-        let translate_servers = match servers.get(&ServerKind::translate) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No translate server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match translate_servers.next().await {
-            Ok(url) => url,
-            Err(e) => {
-                let err_msg = format!("Failed to get the translate server: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
-
-    let translation_service_url = format!("{}v1/audio/translations", translate_server_base_url);
-    dual_info!(
-        "Forward the audio translation request to {} - request_id: {}",
-        translation_service_url,
-        request_id
-    );
-
-    // Create request client
-    let mut request_builder = reqwest::Client::new().post(translation_service_url);
-    for (name, value) in req.headers().iter() {
-        request_builder = request_builder.header(name, value);
-    }
-
     // convert the request body into bytes
-    let body_bytes = hyper::body::to_bytes(req.into_body()).await.map_err(|e| {
+    let (parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await.map_err(|e| {
         let err_msg = format!("Failed to convert the request body into bytes: {}", e);
         dual_error!("{} - request_id: {}", err_msg, request_id);
         ServerError::Operation(err_msg)
     })?;
 
-    let ds_response = request_builder.body(body_bytes).send().await.map_err(|e| {
-        let err_msg = format!(
-            "Failed to forward the request to the downstream server: {}",
-            e
-        );
-        dual_error!("{} - request_id: {}", err_msg, request_id);
-        ServerError::Operation(err_msg)
-    })?;
+    // restrict the candidates to the translate servers matching the label selector
+    let filter =
+        ServerFilter::default().with_selector(label_selector(&parts.headers, &request_id)?);
+
+    // forward the request, failing over to another translate server if necessary
+    let (_translate_server, ds_response) = send_with_retry(
+        &state,
+        ServerKind::translate,
+        &filter,
+        "v1/audio/translations",
+        &request_id,
+        |url| {
+            let mut request_builder = reqwest::Client::new().post(url);
+            for (name, value) in parts.headers.iter() {
+                request_builder = request_builder.header(name, value);
+            }
+            request_builder.body(body_bytes.clone())
+        },
+    )
+    .await?;
 
     let status = ds_response.status();
 
@@ -459,55 +492,34 @@ pub(crate) async fn audio_tts_handler(
         request_id
     );
 
-    // get the tts server
-    let tts_server_base_url = {
-        let servers = state.server_group.read().await;
-        let tts_servers = match servers.get(&ServerKind::tts) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No tts server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match tts_servers.next().await {
-            Ok(url) => url,
-            Err(e) => {
-                let err_msg = format!("Failed to get the tts server: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
-
-    let tts_service_url = format!("{}v1/audio/speech", tts_server_base_url);
-    dual_info!(
-        "Forward the audio speech request to {} - request_id: {}",
-        tts_service_url,
-        request_id
-    );
-
-    // Create request client
-    let mut request_builder = reqwest::Client::new().post(tts_service_url);
-    for (name, value) in req.headers().iter() {
-        request_builder = request_builder.header(name, value);
-    }
-
-    let body_bytes = hyper::body::to_bytes(req.into_body()).await.map_err(|e| {
+    // convert the request body into bytes
+    let (parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await.map_err(|e| {
         let err_msg = format!("Failed to convert the request body into bytes: {}", e);
         dual_error!("{} - request_id: {}", err_msg, request_id);
         ServerError::Operation(err_msg)
     })?;
 
-    let ds_response = request_builder.body(body_bytes).send().await.map_err(|e| {
-        let err_msg = format!(
-            "Failed to forward the request to the downstream server: {}",
-            e
-        );
-        dual_error!("{} - request_id: {}", err_msg, request_id);
-        ServerError::Operation(err_msg)
-    })?;
+    // restrict the candidates to the tts servers matching the label selector
+    let filter =
+        ServerFilter::default().with_selector(label_selector(&parts.headers, &request_id)?);
+
+    // forward the request, failing over to another tts server if necessary
+    let (_tts_server, ds_response) = send_with_retry(
+        &state,
+        ServerKind::tts,
+        &filter,
+        "v1/audio/speech",
+        &request_id,
+        |url| {
+            let mut request_builder = reqwest::Client::new().post(url);
+            for (name, value) in parts.headers.iter() {
+                request_builder = request_builder.header(name, value);
+            }
+            request_builder.body(body_bytes.clone())
+        },
+    )
+    .await?;
 
     // create a response builder with the status and headers of the downstream response
     let mut response_builder = Response::builder().status(ds_response.status());
@@ -552,56 +564,34 @@ pub(crate) async fn image_handler(
 
     dual_info!("Received a new image request - request_id: {}", request_id);
 
-    // get the image server
-    let image_server_base_url = {
-        let servers = state.server_group.read().await;
-        let image_servers = match servers.get(&ServerKind::image) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No image server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match image_servers.next().await {
-            Ok(url) => url,
-            Err(e) => {
-                let err_msg = format!("Failed to get the image server: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
-
-    let image_service_url = format!("{}v1/images/generations", image_server_base_url);
-    dual_info!(
-        "Forward the image request to {} - request_id: {}",
-        image_service_url,
-        request_id
-    );
-
-    // Create request client
-    let mut request_builder = reqwest::Client::new().post(image_service_url);
-    for (name, value) in req.headers().iter() {
-        request_builder = request_builder.header(name, value);
-    }
-
     // convert the request body into bytes
-    let body_bytes = hyper::body::to_bytes(req.into_body()).await.map_err(|e| {
+    let (parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await.map_err(|e| {
         let err_msg = format!("Failed to convert the request body into bytes: {}", e);
         dual_error!("{} - request_id: {}", err_msg, request_id);
         ServerError::Operation(err_msg)
     })?;
 
-    let ds_response = request_builder.body(body_bytes).send().await.map_err(|e| {
-        let err_msg = format!(
-            "Failed to forward the request to the downstream server: {}",
-            e
-        );
-        dual_error!("{} - request_id: {}", err_msg, request_id);
-        ServerError::Operation(err_msg)
-    })?;
+    // restrict the candidates to the image servers matching the label selector
+    let filter =
+        ServerFilter::default().with_selector(label_selector(&parts.headers, &request_id)?);
+
+    // forward the request, failing over to another image server if necessary
+    let (_image_server, ds_response) = send_with_retry(
+        &state,
+        ServerKind::image,
+        &filter,
+        "v1/images/generations",
+        &request_id,
+        |url| {
+            let mut request_builder = reqwest::Client::new().post(url);
+            for (name, value) in parts.headers.iter() {
+                request_builder = request_builder.header(name, value);
+            }
+            request_builder.body(body_bytes.clone())
+        },
+    )
+    .await?;
 
     // create a response builder with the status and headers of the downstream response
     let mut response_builder = Response::builder().status(ds_response.status());
@@ -814,8 +804,14 @@ pub(crate) async fn create_rag_handler(
         request_id
     );
     let embedding_response = {
-        let embedding_request = EmbeddingRequest {
-            model: None,
+        let mut embedding_request = EmbeddingRequest {
+            model: state
+                .config
+                .read()
+                .await
+                .routing
+                .default_embedding_model
+                .clone(),
             input: chunks.as_slice().into(),
             encoding_format: None,
             user: None,
@@ -824,31 +820,19 @@ pub(crate) async fn create_rag_handler(
             vdb_api_key: None,
         };
 
-        // get the embeddings server
-        let servers = state.server_group.read().await;
-        let embeddings_servers = match servers.get(&ServerKind::embeddings) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No embeddings server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        let embeddings_server_base_url = match embeddings_servers.next().await {
-            Ok(url) => url,
-            Err(e) => {
-                let err_msg = format!("Failed to get the embeddings server: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        };
-        let embeddings_service_url = format!("{}v1/embeddings", embeddings_server_base_url);
-        dual_info!(
-            "Forward the embeddings request to {} - request_id: {}",
-            embeddings_service_url,
-            request_id
-        );
+        // restrict the candidates to the embeddings servers serving the requested model
+        let selector = label_selector(&headers, &request_id)?;
+        let route = model_filter(
+            &state,
+            ServerKind::embeddings,
+            embedding_request.model.as_deref(),
+            None,
+            selector,
+            &request_id,
+        )
+        .await?;
+        let filter = route.filter;
+        embedding_request.model = route.model;
 
         // parse the content-type header
         let content_type = headers
@@ -866,19 +850,23 @@ pub(crate) async fn create_rag_handler(
             request_id
         );
 
-        // Create request client
-        let client = reqwest::Client::new();
-        let ds_embedding_response = client
-            .post(embeddings_service_url)
-            .header("Content-Type", content_type)
-            .json(&embedding_request)
-            .send()
-            .await
-            .map_err(|e| {
-                let err_msg = format!("Failed to create the request: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                ServerError::Operation(err_msg)
-            })?;
+        // forward the request, failing over to another embeddings server if necessary
+        let result = send_with_retry(
+            &state,
+            ServerKind::embeddings,
+            &filter,
+            "v1/embeddings",
+            &request_id,
+            |url| {
+                reqwest::Client::new()
+                    .post(url)
+                    .header("Content-Type", content_type.as_str())
+                    .json(&embedding_request)
+            },
+        )
+        .await;
+        record_split(&state, &route.split, &result);
+        let (_embeddings_server, ds_embedding_response) = result?;
 
         ds_embedding_response
             .json::<EmbeddingsResponse>()
@@ -1059,35 +1047,501 @@ pub(crate) async fn chunks_handler(
         })
 }
 
-pub(crate) async fn models_handler(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> ServerResult<Response<Body>> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
-    let models = state.models.read().await;
-    let list_response = ListModelsResponse {
-        object: String::from("list"),
-        data: models.values().flatten().cloned().collect(),
+/// Sends a request to a server of the given kind. If the server cannot be reached or answers
+/// with a retryable status, the request is retried on another server with backoff until the
+/// retry budget configured in `[retry]` is used up.
+async fn send_with_retry<F>(
+    state: &AppState,
+    kind: ServerKind,
+    filter: &ServerFilter,
+    path: &str,
+    request_id: &str,
+    build_request: F,
+) -> ServerResult<(TargetServer, reqwest::Response)>
+where
+    F: Fn(String) -> reqwest::RequestBuilder,
+{
+    let (retry, breaker) = {
+        let config = state.config.read().await;
+        (config.retry.clone(), config.circuit_breaker.clone())
     };
 
-    let json_body = serde_json::to_string(&list_response).map_err(|e| {
-        let err_msg = format!("Failed to serialize the models: {}", e);
-        dual_error!("{} - request_id: {}", err_msg, request_id);
-        ServerError::Operation(err_msg)
-    })?;
-
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "application/json")
-        .body(Body::from(json_body))
-        .map_err(|e| {
-            let err_msg = format!("Failed to create response: {}", e);
-            dual_error!("{} - request_id: {}", err_msg, request_id);
+    let mut filter = filter.clone();
+    let mut attempted: Vec<ServerId> = Vec::new();
+    let mut last_failure: Option<ServerResult<(TargetServer, reqwest::Response)>> = None;
+    loop {
+        // pick a server that has not been tried yet
+        let target = next_server(state, kind, &filter, request_id).await;
+        let target = match (target, last_failure.take()) {
+            (Ok(target), _) => target,
+            (Err(e @ ServerError::TooManyRequests { .. }), None) => return Err(e),
+            // no server is eligible for the request
+            (Err(e @ ServerError::NotFoundServer(_)), None) => {
+                dual_error!(
+                    "Failed to get the {} server: {} - request_id: {}",
+                    kind,
+                    e,
+                    request_id
+                );
+                return Err(e);
+            }
+            (Err(e), None) => {
+                let err_msg = format!("Failed to get the {} server: {}", kind, e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                return Err(ServerError::Operation(err_msg));
+            }
+            // no server left to fail over to
+            (Err(_), Some(failure)) => {
+                dual_error!(
+                    "No {} server left to retry on, attempted servers: {} - request_id: {}",
+                    kind,
+                    attempted.join(", "),
+                    request_id
+                );
+                return failure;
+            }
+        };
+
+        let url = format!("{}{}", target.url, path);
+        dual_info!(
+            "Forward the {} request to {} (server id: {}) - request_id: {}",
+            kind,
+            url,
+            target.id,
+            request_id
+        );
+        attempted.push(target.id.clone());
+        filter.exclude(target.id.clone());
+
+        let failure = match build_request(url)
+            .headers(target.auth_headers.clone())
+            .send()
+            .await
+        {
+            Ok(response) => {
+                // the response headers have arrived, record the latency of the server
+                target.record_latency();
+
+                let status = response.status();
+                target.record_outcome(status.is_server_error(), &breaker);
+
+                if !retry.retry_on_status.contains(&status.as_u16()) {
+                    if attempted.len() > 1 {
+                        dual_info!(
+                            "The {} request succeeded after trying servers: {} - request_id: {}",
+                            kind,
+                            attempted.join(", "),
+                            request_id
+                        );
+                    }
+                    return Ok((target, response));
+                }
+
+                dual_warn!(
+                    "The {} server {} responded with {} - request_id: {}",
+                    kind,
+                    target.id,
+                    status,
+                    request_id
+                );
+                Ok((target, response))
+            }
+            Err(e) => {
+                target.record_outcome(true, &breaker);
+
+                let err_msg = format!(
+                    "Failed to forward the request to the downstream server: {}",
+                    e
+                );
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                Err(ServerError::Operation(err_msg))
+            }
+        };
+
+        // give up once the retry budget of the request is used up
+        let retries = attempted.len() as u32;
+        if retries > retry.max_retries {
+            if retry.max_retries > 0 {
+                dual_error!(
+                    "Retry budget of the {} request exhausted, attempted servers: {} - request_id: {}",
+                    kind,
+                    attempted.join(", "),
+                    request_id
+                );
+            }
+            return failure;
+        }
+
+        // keep only what the final error needs, so that the failed request does not hold a slot
+        // on its server while it backs off or waits in the queue for another one
+        last_failure = Some(match failure {
+            Ok((target, response)) => Ok((target.release(), buffer_response(response).await)),
+            Err(e) => Err(e),
+        });
+
+        let backoff = retry.backoff(retries);
+        dual_warn!(
+            "Retry the {} request on another server in {} ms ({}/{}) - request_id: {}",
+            kind,
+            backoff.as_millis(),
+            retries,
+            retry.max_retries,
+            request_id
+        );
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+// Read the body of a response, so that the connection to the server it came from can be released
+async fn buffer_response(response: reqwest::Response) -> reqwest::Response {
+    let mut buffered = hyper::Response::builder().status(response.status());
+    if let Some(headers) = buffered.headers_mut() {
+        *headers = response.headers().clone();
+    }
+    let body = response.bytes().await.unwrap_or_default();
+
+    // the status and headers come from a valid response
+    reqwest::Response::from(buffered.body(body).unwrap())
+}
+
+/// Picks a server of the given kind. If every server matching the request is saturated, the
+/// request waits in the queue of the server group for a free slot, as configured in `[queue]`.
+async fn next_server(
+    state: &AppState,
+    kind: ServerKind,
+    filter: &ServerFilter,
+    request_id: &str,
+) -> ServerResult<TargetServer> {
+    let config = state.config.read().await.queue.clone();
+
+    let mut ticket = None;
+    let deadline = Instant::now() + Duration::from_secs(config.timeout);
+    loop {
+        let (target, queue) = {
+            let servers = state.server_group.read().await;
+            match servers.get(&kind) {
+                Some(group) => (group.next(filter).await, group.queue()),
+                None => return Err(ServerError::NotFoundServer(kind.to_string())),
+            }
+        };
+
+        let message = match target {
+            Err(ServerError::TooManyRequests { message, .. }) => message,
+            target => return target,
+        };
+
+        // join the queue of the group, unless it is full
+        if ticket.is_none() {
+            match queue.join(config.max_size) {
+                Some(joined) => {
+                    dual_info!(
+                        "All {} servers are busy, the request waits in the queue ({} waiting) - request_id: {}",
+                        kind,
+                        queue.depth(),
+                        request_id
+                    );
+                    ticket = Some(joined);
+                }
+                None => {
+                    let err_msg = format!("{}, and the queue is full", message);
+                    dual_warn!("{} - request_id: {}", err_msg, request_id);
+                    return Err(ServerError::TooManyRequests {
+                        message: err_msg,
+                        retry_after: config.retry_after,
+                    });
+                }
+            }
+        }
+
+        // give up once the queue timeout passes
+        let now = Instant::now();
+        if now >= deadline {
+            let err_msg = format!(
+                "{}, no slot was freed within {} seconds",
+                message, config.timeout
+            );
+            dual_warn!("{} - request_id: {}", err_msg, request_id);
+            return Err(ServerError::TooManyRequests {
+                message: err_msg,
+                retry_after: config.retry_after,
+            });
+        }
+
+        queue.wait(deadline - now).await;
+    }
+}
+
+// Parse the label selector sent in the `x-nexus-selector` header, if any
+fn label_selector(headers: &HeaderMap, request_id: &str) -> ServerResult<Option<LabelSelector>> {
+    let selector = match headers.get(SELECTOR_HEADER) {
+        Some(selector) => selector,
+        None => return Ok(None),
+    };
+
+    let selector = selector
+        .to_str()
+        .map_err(|e| ServerError::BadRequest(format!("Invalid label selector: {}", e)))
+        .and_then(|selector| selector.parse::<LabelSelector>());
+    match selector {
+        Ok(selector) => Ok(Some(selector)),
+        Err(e) => {
+            dual_error!("{} - request_id: {}", e, request_id);
+            Err(e)
+        }
+    }
+}
+
+// Restrict a filter of chat servers to the servers whose context window holds the request.
+// Servers that do not report their context size are kept.
+async fn context_filter(
+    state: &AppState,
+    mut filter: ServerFilter,
+    request: &ChatCompletionRequest,
+    request_id: &str,
+) -> ServerResult<ServerFilter> {
+    let server_ids = match &filter.server_ids {
+        Some(server_ids) => server_ids.clone(),
+        None => match state.server_group.read().await.get(&ServerKind::chat) {
+            Some(group) => group.server_ids().await.into_iter().collect(),
+            None => return Ok(filter),
+        },
+    };
+
+    let ctx_sizes = {
+        let server_info = state.server_info.read().await;
+        server_ids
+            .into_iter()
+            .map(|server_id| {
+                let ctx_size = server_info
+                    .servers
+                    .get(&server_id)
+                    .and_then(|api_server| api_server.chat_model.as_ref())
+                    .and_then(|chat_model| chat_model.ctx_size)
+                    .filter(|ctx_size| *ctx_size > 0);
+                (server_id, ctx_size)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // tokenizing is expensive and blocks the runtime, so skip it if the request fits into every
+    // context window anyway
+    let upper_bound = context::request_tokens_upper_bound(request);
+    if ctx_sizes
+        .iter()
+        .all(|(_, ctx_size)| ctx_size.is_none_or(|ctx_size| ctx_size >= upper_bound))
+    {
+        return Ok(filter);
+    }
+
+    let required_tokens = context::estimate_request_tokens(request);
+
+    let mut largest_ctx_size = None;
+    let mut fitting = HashSet::new();
+    for (server_id, ctx_size) in ctx_sizes {
+        if let Some(ctx_size) = ctx_size {
+            largest_ctx_size = largest_ctx_size.max(Some(ctx_size));
+            if ctx_size < required_tokens {
+                continue;
+            }
+        }
+        fitting.insert(server_id);
+    }
+
+    if let (true, Some(largest_ctx_size)) = (fitting.is_empty(), largest_ctx_size) {
+        let prompt_tokens = context::estimate_prompt_tokens(request);
+        let err_msg = format!(
+            "This model's maximum context length is {} tokens. However, you requested about {} tokens ({} in the messages, {} in the completion). Please reduce the length of the messages or completion.",
+            largest_ctx_size,
+            required_tokens,
+            prompt_tokens,
+            required_tokens - prompt_tokens
+        );
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::ContextLengthExceeded(err_msg));
+    }
+
+    filter.server_ids = Some(fitting);
+    Ok(filter)
+}
+
+/// Model a request is routed to
+pub(crate) struct ModelRoute {
+    /// Model to forward to the servers
+    pub(crate) model: Option<String>,
+    /// Restricts the candidates to the servers serving the model
+    pub(crate) filter: ServerFilter,
+    /// Alias and arm of the traffic split the request is counted in
+    split: Option<(String, CanaryArm)>,
+}
+
+// Resolve the model of a request and build a filter that restricts the servers of the given kind
+// to the ones serving it and matching the label selector. An alias configured in `[[models]]`
+// resolves to its canary model for the share of the users sent to the canary, and otherwise to
+// the first model of its chain that has an available server.
+async fn model_filter(
+    state: &AppState,
+    kind: ServerKind,
+    model: Option<&str>,
+    user: Option<&str>,
+    selector: Option<LabelSelector>,
+    request_id: &str,
+) -> ServerResult<ModelRoute> {
+    let model = match model {
+        Some(model) => model,
+        None => {
+            return Ok(ModelRoute {
+                model: None,
+                filter: ServerFilter::default().with_selector(selector),
+                split: None,
+            })
+        }
+    };
+
+    let alias = match state.config.read().await.model_alias(model).cloned() {
+        Some(alias) => alias,
+        None => {
+            let server_ids = state.servers_for_model(kind, model).await?;
+            return Ok(ModelRoute {
+                model: Some(model.to_string()),
+                filter: ServerFilter::with_server_ids(server_ids).with_selector(selector),
+                split: None,
+            });
+        }
+    };
+    let split = |arm| alias.canary.as_ref().map(|_| (alias.name.clone(), arm));
+
+    // a request sent to the canary falls back to the chain of the alias if the canary model has
+    // no available server
+    let canary = alias
+        .canary
+        .as_ref()
+        .filter(|_| canary::pick_arm(&alias.name, user, alias.canary_percent) == CanaryArm::Canary);
+    let chain = canary.into_iter().chain(alias.chain()).collect::<Vec<_>>();
+    for (idx, candidate) in chain.iter().enumerate() {
+        if let Some(filter) = available_model_filter(state, kind, candidate, &selector).await {
+            if idx > 0 {
+                dual_warn!(
+                    "No healthy {} server serves the model {}, fall back to the model {} for the alias {} - request_id: {}",
+                    kind,
+                    chain[0],
+                    candidate,
+                    model,
+                    request_id
+                );
+            }
+            dual_info!(
+                "Resolved the model alias {} to the model {} - request_id: {}",
+                model,
+                candidate,
+                request_id
+            );
+
+            let arm = match (canary.is_some(), idx) {
+                (true, 0) => CanaryArm::Canary,
+                _ => CanaryArm::Stable,
+            };
+            return Ok(ModelRoute {
+                model: Some(candidate.to_string()),
+                filter,
+                split: split(arm),
+            });
+        }
+    }
+
+    // no model of the chain is available, route to the primary model to report why
+    let server_ids = state.servers_for_model(kind, &alias.model).await?;
+    Ok(ModelRoute {
+        model: Some(alias.model.clone()),
+        filter: ServerFilter::with_server_ids(server_ids).with_selector(selector),
+        split: split(CanaryArm::Stable),
+    })
+}
+
+// Count a request in the arm of the traffic split it was routed to. The request failed if it
+// could not be forwarded or the server responded with a server error.
+fn record_split(
+    state: &AppState,
+    split: &Option<(String, CanaryArm)>,
+    result: &ServerResult<(TargetServer, reqwest::Response)>,
+) {
+    if let Some((alias, arm)) = split {
+        let failed = match result {
+            Ok((_, response)) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        state.canaries.record(alias, *arm, failed);
+    }
+}
+
+// Build the filter of the servers serving the model if one of them can take requests
+async fn available_model_filter(
+    state: &AppState,
+    kind: ServerKind,
+    model: &str,
+    selector: &Option<LabelSelector>,
+) -> Option<ServerFilter> {
+    let server_ids = state.servers_for_model(kind, model).await.ok()?;
+    let filter = ServerFilter::with_server_ids(server_ids).with_selector(selector.clone());
+
+    let servers = state.server_group.read().await;
+    match servers.get(&kind)?.is_available(&filter).await {
+        true => Some(filter),
+        false => None,
+    }
+}
+
+pub(crate) async fn models_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> ServerResult<Response<Body>> {
+    let request_id = headers
+        .get("x-request-id")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("unknown")
+        .to_string();
+
+    let mut data: Vec<Model> = state
+        .models
+        .read()
+        .await
+        .values()
+        .flatten()
+        .cloned()
+        .collect();
+
+    // list the aliases configured in `[models]` like the models they stand for
+    for alias in state.config.read().await.models.iter() {
+        let created = data
+            .iter()
+            .find(|model| model.id == alias.model)
+            .map(|model| model.created)
+            .unwrap_or_default();
+        data.push(Model {
+            id: alias.name.clone(),
+            created,
+            object: String::from("model"),
+            owned_by: String::from("llama-nexus"),
+        });
+    }
+
+    let list_response = ListModelsResponse {
+        object: String::from("list"),
+        data,
+    };
+
+    let json_body = serde_json::to_string(&list_response).map_err(|e| {
+        let err_msg = format!("Failed to serialize the models: {}", e);
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json_body))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create response: {}", e);
+            dual_error!("{} - request_id: {}", err_msg, request_id);
             ServerError::Operation(err_msg)
         })
 }
@@ -1158,6 +1612,7 @@ pub mod admin {
     pub async fn register_downstream_server_handler(
         State(state): State<Arc<AppState>>,
         headers: HeaderMap,
+        Query(options): Query<RegisterOptions>,
         Json(server): Json<Server>,
     ) -> ServerResult<Response<Body>> {
         // Get request ID from headers
@@ -1167,41 +1622,23 @@ pub mod admin {
             .unwrap_or("unknown")
             .to_string();
 
-        let server_url = server.url.clone();
-        let server_kind = server.kind;
-        let server_id = server.id.clone();
-
-        // verify the server
-        if server_kind.contains(ServerKind::chat)
-            || server_kind.contains(ServerKind::embeddings)
-            || server_kind.contains(ServerKind::image)
-            || server_kind.contains(ServerKind::transcribe)
-            || server_kind.contains(ServerKind::translate)
-            || server_kind.contains(ServerKind::tts)
-        {
-            verify_server(
-                State(state.clone()),
-                &request_id,
-                &server_id,
-                &server_url,
-                &server_kind,
-            )
-            .await?;
-        }
-
-        // register the server
-        state.register_downstream_server(server).await?;
+        // verify and register the server
+        let server =
+            verify_and_register(state.clone(), &request_id, server, options.replace).await?;
         dual_info!(
             "Registered successfully. Assigned Server Id: {} - request_id: {}",
-            server_id,
+            server.id,
             request_id
         );
 
+        // persist the registry. A failure is logged, but does not undo the change.
+        let _ = registry::save(&state).await;
+
         // create a response with status code 200. Content-Type is JSON
         let json_body = serde_json::json!({
-            "id": server_id,
-            "url": server_url,
-            "kind": server_kind
+            "id": server.id,
+            "url": server.url,
+            "kind": server.kind
         });
 
         let response = axum::response::Response::builder()
@@ -1217,139 +1654,294 @@ pub mod admin {
         Ok(response)
     }
 
-    // verify the server and get the server info and model list
-    async fn verify_server(
-        State(state): State<Arc<AppState>>,
+    /// Verifies a server and registers it. If `replace` is set, a server already registered
+    /// with the same url is replaced, otherwise the registration is rejected. A server registered
+    /// without a kind gets the kind inferred from its server info. Returns the registered server.
+    pub(crate) async fn verify_and_register(
+        state: Arc<AppState>,
         request_id: impl AsRef<str>,
-        server_id: impl AsRef<str>,
-        server_url: impl AsRef<str>,
-        server_kind: &ServerKind,
-    ) -> ServerResult<()> {
-        let request_id = request_id.as_ref();
-        let server_url = server_url.as_ref();
-        let server_id = server_id.as_ref();
-
-        let client = reqwest::Client::new();
-
-        let server_info_url = format!("{}/v1/info", server_url);
-        let response = client.get(&server_info_url).send().await.map_err(|e| {
+        mut server: Server,
+        replace: bool,
+    ) -> ServerResult<Server> {
+        // check for a duplicate before contacting the server
+        let existing = state.find_server_by_url(&server.url).await;
+        if let (Some(existing), false) = (&existing, replace) {
             let err_msg = format!(
-                "Failed to verify the {} downstream server: {}",
-                server_kind, e
+                "The url {} is already registered by server {}",
+                server.url, existing.id
             );
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            ServerError::Operation(err_msg)
-        })?;
+            dual_error!("{} - request_id: {}", err_msg, request_id.as_ref());
+            return Err(ServerError::DuplicateServer(err_msg));
+        }
 
-        if !response.status().is_success() {
-            let err_msg = format!(
-                "Failed to verify the {} downstream server: {}",
-                server_kind,
-                response.status()
+        // verify the server
+        let (mut api_server, server_models) =
+            fetch_server_metadata(request_id.as_ref(), &server).await?;
+
+        // infer the kind of the server from the models it reports
+        if server.kind.is_empty() {
+            server.kind = api_server.kind();
+            if server.kind.is_empty() {
+                let err_msg = format!(
+                    "Failed to detect the kind of the server at {}: the server reports no model",
+                    server.url
+                );
+                dual_error!("{} - request_id: {}", err_msg, request_id.as_ref());
+                return Err(ServerError::Operation(err_msg));
+            }
+
+            server.id = Server::generate_id(server.kind);
+            api_server.server_id = Some(server.id.clone());
+            dual_info!(
+                "Detected the kind of server {}: {} - request_id: {}",
+                server.id,
+                server.kind,
+                request_id.as_ref()
             );
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg));
         }
 
-        let mut api_server = response.json::<ApiServer>().await.map_err(|e| {
-            let err_msg = format!("Failed to parse the server info: {}", e);
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            ServerError::Operation(err_msg)
-        })?;
-        api_server.server_id = Some(server_id.to_string());
-
-        dual_info!("server kind: {}", server_kind.to_string());
-        dual_info!("api server: {:?}", api_server);
+        // register the server, replacing the server registered with the same url only once it
+        // succeeds. Its metadata is stored afterwards so that a failed registration, e.g. a
+        // concurrent registration of the same url, leaves no metadata behind.
+        match existing {
+            Some(existing) => {
+                state
+                    .replace_downstream_server(&existing.id, server.clone())
+                    .await?;
+                dual_info!(
+                    "Replaced server {} by server {} - request_id: {}",
+                    existing.id,
+                    server.id,
+                    request_id.as_ref()
+                );
+            }
+            None => state.register_downstream_server(server.clone()).await?,
+        }
 
-        // verify the server kind
+        // requests for a model named like a model alias are routed by the alias
         {
-            if server_kind.contains(ServerKind::chat) && api_server.chat_model.is_none() {
-                let err_msg = "You are trying to register a chat server. However, the server does not support `chat`. Please check the server kind.";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-            if server_kind.contains(ServerKind::embeddings) && api_server.embedding_model.is_none()
-            {
-                let err_msg = "You are trying to register an embedding server. However, the server does not support `embeddings`. Please check the server kind.";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-            if server_kind.contains(ServerKind::image) && api_server.image_model.is_none() {
-                let err_msg = "You are trying to register an image server. However, the server does not support `image`. Please check the server kind.";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-            if server_kind.contains(ServerKind::tts) && api_server.tts_model.is_none() {
-                let err_msg = "You are trying to register a TTS server. However, the server does not support `tts`. Please check the server kind.";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-            if server_kind.contains(ServerKind::translate) && api_server.translate_model.is_none() {
-                let err_msg = "You are trying to register a translation server. However, the server does not support `translate`. Please check the server kind.";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-            if server_kind.contains(ServerKind::transcribe) && api_server.transcribe_model.is_none()
-            {
-                let err_msg = "You are trying to register a transcription server. However, the server does not support `transcribe`. Please check the server kind.";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+            let config = state.config.read().await;
+            for model in server_models.iter() {
+                if config.model_alias(&model.id).is_some() {
+                    dual_warn!(
+                        "The model {} of server {} is shadowed by the model alias of the same name - request_id: {}",
+                        model.id,
+                        server.id,
+                        request_id.as_ref()
+                    );
+                }
             }
         }
+        store_server_metadata(&state, &server.id, api_server, server_models).await;
+
+        Ok(server)
+    }
 
+    // store the server info and the model list of a server
+    async fn store_server_metadata(
+        state: &AppState,
+        server_id: &ServerId,
+        api_server: ApiServer,
+        server_models: Vec<Model>,
+    ) {
         // update the server info
         let server_info = &mut state.server_info.write().await;
-        server_info
-            .servers
-            .insert(server_id.to_string(), api_server);
-
-        // get the models from the downstream server
-        let list_models_url = format!("{}/v1/models", server_url);
-        let list_models_response = client.get(&list_models_url).send().await.map_err(|e| {
-            let err_msg = format!("Failed to get the models from the downstream server: {}", e);
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            ServerError::Operation(err_msg)
-        })?;
-
-        let list_models_response = list_models_response
-            .json::<ListModelsResponse>()
-            .await
-            .map_err(|e| {
-                let err_msg = format!("Failed to parse the models: {}", e);
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                ServerError::Operation(err_msg)
-            })?;
+        server_info.servers.insert(server_id.clone(), api_server);
 
         // update the models
         let mut models = state.models.write().await;
-        models.insert(server_id.to_string(), list_models_response.data);
-
-        Ok(())
+        models.insert(server_id.clone(), server_models);
     }
 
-    pub async fn remove_downstream_server_handler(
-        State(state): State<Arc<AppState>>,
-        headers: HeaderMap,
-        Json(server_id): Json<ServerIdToRemove>,
-    ) -> ServerResult<Response<Body>> {
-        // Get request ID from headers
-        let request_id = headers
-            .get("x-request-id")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("unknown")
-            .to_string();
+    /// Seconds to wait for a server to report its server info and models
+    const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
 
-        state
-            .unregister_downstream_server(&server_id.server_id)
-            .await?;
+    /// Fetches the server info and the model list of a server and checks them against its kind.
+    /// The server info of an OpenAI-compatible server is built from its models and metadata.
+    /// Unreachable servers fail after `VERIFY_TIMEOUT`.
+    pub(crate) async fn fetch_server_metadata(
+        request_id: impl AsRef<str>,
+        server: &Server,
+    ) -> ServerResult<(ApiServer, Vec<Model>)> {
+        let request_id = request_id.as_ref();
 
-        // create a response with status code 200. Content-Type is JSON
-        let json_body = serde_json::json!({
-            "message": "Server unregistered successfully.",
-            "id": server_id.server_id,
-        });
+        match tokio::time::timeout(VERIFY_TIMEOUT, request_server_metadata(request_id, server))
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                let err_msg = format!(
+                    "Failed to verify the {} downstream server: timed out after {} seconds",
+                    server.kind,
+                    VERIFY_TIMEOUT.as_secs()
+                );
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                Err(ServerError::Operation(err_msg))
+            }
+        }
+    }
 
-        let response = Response::builder()
+    // Request the server info and the model list of a server
+    async fn request_server_metadata(
+        request_id: &str,
+        server: &Server,
+    ) -> ServerResult<(ApiServer, Vec<Model>)> {
+        let server_url = server.url.trim_end_matches('/');
+        let server_id = server.id.as_str();
+        let server_kind = &server.kind;
+
+        let client = reqwest::Client::new();
+
+        let api_server = match server.flavor {
+            ServerFlavor::LlamaEdge => {
+                let server_info_url = format!("{}/v1/info", server_url);
+                let response = server
+                    .authorize(client.get(&server_info_url))
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        let err_msg = format!(
+                            "Failed to verify the {} downstream server: {}",
+                            server_kind, e
+                        );
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        ServerError::Operation(err_msg)
+                    })?;
+
+                if !response.status().is_success() {
+                    let err_msg = format!(
+                        "Failed to verify the {} downstream server: {}",
+                        server_kind,
+                        response.status()
+                    );
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    return Err(ServerError::Operation(err_msg));
+                }
+
+                let mut api_server = response.json::<ApiServer>().await.map_err(|e| {
+                    let err_msg = format!("Failed to parse the server info: {}", e);
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    ServerError::Operation(err_msg)
+                })?;
+                api_server.server_id = Some(server_id.to_string());
+
+                dual_info!("server kind: {}", server_kind.to_string());
+                dual_info!("api server: {:?}", api_server);
+
+                // verify the server kind
+                {
+                    if server_kind.contains(ServerKind::chat) && api_server.chat_model.is_none() {
+                        let err_msg = "You are trying to register a chat server. However, the server does not support `chat`. Please check the server kind.";
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg.to_string()));
+                    }
+                    if server_kind.contains(ServerKind::embeddings)
+                        && api_server.embedding_model.is_none()
+                    {
+                        let err_msg = "You are trying to register an embedding server. However, the server does not support `embeddings`. Please check the server kind.";
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg.to_string()));
+                    }
+                    if server_kind.contains(ServerKind::image) && api_server.image_model.is_none() {
+                        let err_msg = "You are trying to register an image server. However, the server does not support `image`. Please check the server kind.";
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg.to_string()));
+                    }
+                    if server_kind.contains(ServerKind::tts) && api_server.tts_model.is_none() {
+                        let err_msg = "You are trying to register a TTS server. However, the server does not support `tts`. Please check the server kind.";
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg.to_string()));
+                    }
+                    if server_kind.contains(ServerKind::translate)
+                        && api_server.translate_model.is_none()
+                    {
+                        let err_msg = "You are trying to register a translation server. However, the server does not support `translate`. Please check the server kind.";
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg.to_string()));
+                    }
+                    if server_kind.contains(ServerKind::transcribe)
+                        && api_server.transcribe_model.is_none()
+                    {
+                        let err_msg = "You are trying to register a transcription server. However, the server does not support `transcribe`. Please check the server kind.";
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg.to_string()));
+                    }
+                }
+
+                Some(api_server)
+            }
+            // OpenAI-compatible servers have no `/v1/info` endpoint
+            ServerFlavor::OpenAI => None,
+        };
+
+        // get the models from the downstream server
+        let list_models_url = format!("{}/v1/models", server_url);
+        let list_models_response = server
+            .authorize(client.get(&list_models_url))
+            .send()
+            .await
+            .map_err(|e| {
+                let err_msg = format!("Failed to get the models from the downstream server: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        let list_models_response = list_models_response
+            .json::<ListModelsResponse>()
+            .await
+            .map_err(|e| {
+                let err_msg = format!("Failed to parse the models: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        let api_server = match api_server {
+            Some(api_server) => api_server,
+            None => {
+                if list_models_response.data.is_empty() {
+                    let err_msg = format!(
+                        "Failed to verify the {} downstream server: the server serves no model",
+                        server_kind
+                    );
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    return Err(ServerError::Operation(err_msg));
+                }
+
+                let api_server = ApiServer::from_openai_models(server, &list_models_response.data);
+                dual_info!("api server: {:?}", api_server);
+                api_server
+            }
+        };
+
+        Ok((api_server, list_models_response.data))
+    }
+
+    pub async fn remove_downstream_server_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Json(server_id): Json<ServerIdToRemove>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        state
+            .unregister_downstream_server(&server_id.server_id)
+            .await?;
+
+        // persist the registry. A failure is logged, but does not undo the change.
+        let _ = registry::save(&state).await;
+
+        // create a response with status code 200. Content-Type is JSON
+        let json_body = serde_json::json!({
+            "message": "Server unregistered successfully.",
+            "id": server_id.server_id,
+        });
+
+        let response = Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "application/json")
             .body(Body::from(json_body.to_string()))
@@ -1365,6 +1957,7 @@ pub mod admin {
     pub async fn list_downstream_servers_handler(
         State(state): State<Arc<AppState>>,
         headers: HeaderMap,
+        Query(options): Query<ListOptions>,
     ) -> ServerResult<Response<Body>> {
         dual_debug!("list_downstream_servers_handler");
 
@@ -1375,7 +1968,19 @@ pub mod admin {
             .unwrap_or("unknown")
             .to_string();
 
-        let servers = state.list_downstream_servers().await?;
+        let mut servers = state.list_downstream_servers().await?;
+
+        // keep the servers whose labels match the selector
+        if let Some(selector) = &options.selector {
+            let selector = selector.parse::<LabelSelector>().map_err(|e| {
+                dual_error!("{} - request_id: {}", e, request_id);
+                e
+            })?;
+            servers.retain(|_, servers| {
+                servers.retain(|status| selector.matches(&status.server.labels));
+                !servers.is_empty()
+            });
+        }
 
         // compute the total number of servers
         let total_servers = servers.values().fold(0, |acc, servers| acc + servers.len());
@@ -1399,4 +2004,551 @@ pub mod admin {
 
         Ok(response)
     }
+
+    pub async fn export_servers_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let servers = registry::export(&state).await;
+        dual_info!(
+            "Exported {} downstream servers - request_id: {}",
+            servers.len(),
+            request_id
+        );
+
+        let json_body = serde_json::json!({ "servers": servers });
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn import_servers_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Query(options): Query<RegisterOptions>,
+        Json(request): Json<ImportRequest>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        // verify and register the servers in parallel
+        let results =
+            registry::import(state.clone(), &request_id, request.servers, options.replace).await;
+        let registered = results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .count();
+        let failed = results.len() - registered;
+        dual_info!(
+            "Imported {} downstream servers, {} failed - request_id: {}",
+            registered,
+            failed,
+            request_id
+        );
+
+        // persist the registry. A failure is logged, but does not undo the change.
+        if registered > 0 {
+            let _ = registry::save(&state).await;
+        }
+
+        let json_body = serde_json::json!({
+            "registered": registered,
+            "failed": failed,
+            "servers": results,
+        });
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn update_downstream_server_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Path(server_id): Path<ServerId>,
+        Json(update): Json<ServerUpdate>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        if update.weight == Some(0) {
+            let err_msg = "The weight of a server must be greater than 0";
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            return Err(ServerError::BadRequest(err_msg.to_string()));
+        }
+        if update.max_concurrency == Some(Some(0)) {
+            let err_msg = "The max_concurrency of a server must be greater than 0";
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            return Err(ServerError::BadRequest(err_msg.to_string()));
+        }
+        if update.kind.is_some_and(|kind| kind.is_empty()) {
+            let err_msg = "The kind of a server must not be empty";
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            return Err(ServerError::BadRequest(err_msg.to_string()));
+        }
+        if let Some(labels) = &update.labels {
+            validate_labels(labels).map_err(|err_msg| {
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::BadRequest(err_msg)
+            })?;
+        }
+
+        let current = state.get_server(&server_id).await.map_err(|e| {
+            dual_error!(
+                "Failed to update the server: {} - request_id: {}",
+                e,
+                request_id
+            );
+            e
+        })?;
+
+        let mut updated = current.clone();
+        update.apply(&mut updated);
+
+        // reject a url that is already registered by another server before contacting it
+        if let Some(existing) = state.find_server_by_url(&updated.url).await {
+            if existing.id != server_id {
+                let err_msg = format!(
+                    "The url {} is already registered by server {}",
+                    updated.url, existing.id
+                );
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                return Err(ServerError::DuplicateServer(err_msg));
+            }
+        }
+
+        // verify the server again if it moves or serves other kinds
+        let metadata = match updated.url != current.url || updated.kind != current.kind {
+            true => Some(fetch_server_metadata(&request_id, &updated).await?),
+            false => None,
+        };
+
+        // update the server
+        let server = state.update_downstream_server(&server_id, &update).await?;
+        dual_info!("Updated server {} - request_id: {}", server_id, request_id);
+
+        // describe the server by the metadata of its new url or kind once the update succeeded
+        if let Some((api_server, server_models)) = metadata {
+            store_server_metadata(&state, &server.id, api_server, server_models).await;
+        }
+
+        // persist the registry. A failure is logged, but does not undo the change.
+        let _ = registry::save(&state).await;
+
+        let json_body = serde_json::to_string(&server).unwrap();
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn heartbeat_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Path(server_id): Path<ServerId>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let expires_in = state.renew_lease(&server_id).await.map_err(|e| {
+            dual_error!(
+                "Failed to renew the lease: {} - request_id: {}",
+                e,
+                request_id
+            );
+            e
+        })?;
+        dual_debug!(
+            "Renewed the lease of server {} - request_id: {}",
+            server_id,
+            request_id
+        );
+
+        // create a response with status code 200. Content-Type is JSON
+        let json_body = serde_json::json!({
+            "message": "Lease renewed successfully.",
+            "id": server_id,
+            "expires_in": expires_in,
+        });
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn refresh_server_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Path(server_id): Path<ServerId>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let server = state.get_server(&server_id).await.map_err(|e| {
+            dual_error!(
+                "Failed to refresh the server: {} - request_id: {}",
+                e,
+                request_id
+            );
+            e
+        })?;
+
+        // re-pull the server info and the model list
+        let report = refresh::refresh_server(&state, &server, &request_id).await?;
+        dual_info!(
+            "Refreshed server {} ({} change(s)) - request_id: {}",
+            server_id,
+            report.num_changes(),
+            request_id
+        );
+
+        let json_body = serde_json::to_string(&report).unwrap();
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn drain_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Path(server_id): Path<ServerId>,
+        request: Option<Json<DrainRequest>>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let request = request.map(|Json(request)| request).unwrap_or_default();
+
+        let server = state.get_server(&server_id).await.map_err(|e| {
+            dual_error!(
+                "Failed to drain the server: {} - request_id: {}",
+                e,
+                request_id
+            );
+            e
+        })?;
+
+        // stop routing new requests to the server
+        server.set_draining(true);
+        let in_flight = server.in_flight();
+        dual_info!(
+            "Draining server {} with {} request(s) in flight - request_id: {}",
+            server_id,
+            in_flight,
+            request_id
+        );
+
+        if request.unregister {
+            tokio::spawn(drain::unregister_when_drained(
+                state.clone(),
+                server,
+                request.timeout_seconds.map(std::time::Duration::from_secs),
+            ));
+        }
+
+        // create a response with status code 200. Content-Type is JSON
+        let json_body = serde_json::json!({
+            "message": "Server is draining.",
+            "id": server_id,
+            "in_flight": in_flight,
+            "unregister": request.unregister,
+        });
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn undrain_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Path(server_id): Path<ServerId>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let server = state.get_server(&server_id).await.map_err(|e| {
+            dual_error!(
+                "Failed to undrain the server: {} - request_id: {}",
+                e,
+                request_id
+            );
+            e
+        })?;
+
+        // route new requests to the server again
+        server.set_draining(false);
+        dual_info!(
+            "Undrained server {} - request_id: {}",
+            server_id,
+            request_id
+        );
+
+        // create a response with status code 200. Content-Type is JSON
+        let json_body = serde_json::json!({
+            "message": "Server is no longer draining.",
+            "id": server_id,
+            "in_flight": server.in_flight(),
+        });
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+
+        Ok(response)
+    }
+
+    pub async fn get_routing_strategy_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let strategies = state.routing_strategies().await;
+
+        let json_body = serde_json::to_string(&strategies).map_err(|e| {
+            let err_msg = format!("Failed to serialize the routing strategies: {}", e);
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })
+    }
+
+    pub async fn get_queues_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let queues = state.queue_statuses().await;
+
+        let json_body = serde_json::to_string(&queues).map_err(|e| {
+            let err_msg = format!("Failed to serialize the queues: {}", e);
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })
+    }
+
+    pub async fn get_canaries_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let canaries = state.canary_statuses().await;
+
+        let json_body = serde_json::to_string(&canaries).map_err(|e| {
+            let err_msg = format!("Failed to serialize the traffic splits: {}", e);
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })
+    }
+
+    pub async fn set_canary_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Json(update): Json<CanaryUpdate>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        if update.canary_percent > 100 {
+            let err_msg = "The canary percentage must be between 0 and 100";
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            return Err(ServerError::BadRequest(err_msg.to_string()));
+        }
+
+        let status = state.set_canary(update).await?;
+
+        // create a response with status code 200. Content-Type is JSON
+        let json_body = serde_json::json!({
+            "message": "Traffic split updated successfully.",
+            "split": status,
+        });
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })
+    }
+
+    pub async fn set_routing_strategy_handler(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Json(update): Json<RoutingStrategyUpdate>,
+    ) -> ServerResult<Response<Body>> {
+        // Get request ID from headers
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        if update.kind.is_empty() {
+            let err_msg = "The server kind must not be empty";
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            return Err(ServerError::BadRequest(err_msg.to_string()));
+        }
+
+        state
+            .set_routing_strategy(update.kind, update.strategy)
+            .await;
+
+        // create a response with status code 200. Content-Type is JSON
+        let json_body = serde_json::json!({
+            "message": "Routing strategy updated successfully.",
+            "kind": update.kind,
+            "strategy": update.strategy,
+        });
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {}", e);
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })
+    }
 }
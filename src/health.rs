@@ -0,0 +1,165 @@
+use crate::{
+    config::HealthCheckConfig,
+    dual_info, dual_warn,
+    server::{Server, ServerFlavor},
+    AppState,
+};
+use serde::Serialize;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Health state of a downstream server maintained by the active health checker
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct HealthStatus {
+    pub(crate) healthy: bool,
+    /// Unix timestamp (seconds) of the last probe, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last_probe: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last_error: Option<String>,
+    #[serde(skip)]
+    consecutive_failures: u32,
+    #[serde(skip)]
+    consecutive_successes: u32,
+}
+impl Default for HealthStatus {
+    fn default() -> Self {
+        Self {
+            healthy: true,
+            last_probe: None,
+            last_error: None,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+        }
+    }
+}
+impl HealthStatus {
+    /// Records the result of a probe. Returns the new health state if it changed.
+    pub(crate) fn record(
+        &mut self,
+        result: Result<(), String>,
+        config: &HealthCheckConfig,
+    ) -> Option<bool> {
+        self.last_probe = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+
+        match result {
+            Ok(()) => {
+                self.consecutive_failures = 0;
+                self.consecutive_successes = self.consecutive_successes.saturating_add(1);
+                self.last_error = None;
+
+                if !self.healthy && self.consecutive_successes >= config.healthy_threshold {
+                    self.healthy = true;
+                    return Some(true);
+                }
+            }
+            Err(e) => {
+                self.consecutive_successes = 0;
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+                self.last_error = Some(e);
+
+                if self.healthy && self.consecutive_failures >= config.unhealthy_threshold {
+                    self.healthy = false;
+                    return Some(false);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Periodically probes every registered server and updates its health state
+pub(crate) async fn run_health_checker(state: Arc<AppState>) {
+    dual_info!("Health checker started");
+
+    loop {
+        let config = state.config.read().await.health_check.clone();
+        tokio::time::sleep(Duration::from_secs(config.interval.max(1))).await;
+
+        let servers = state.downstream_servers().await;
+        let client = reqwest::Client::new();
+        let results = futures_util::future::join_all(
+            servers.iter().map(|server| probe(&client, server, &config)),
+        )
+        .await;
+
+        for (server, result) in servers.iter().zip(results) {
+            if let Err(e) = &result {
+                dual_warn!("Health probe of {} failed: {}", server.id, e);
+            }
+
+            // the server may have been unregistered while it was probed
+            let changed = match state.health.write().await.get_mut(&server.id) {
+                Some(health) => health.record(result, &config),
+                None => continue,
+            };
+
+            if let Some(healthy) = changed {
+                state.set_server_health(&server.id, healthy).await;
+            }
+        }
+    }
+}
+
+// Probe a single server
+async fn probe(
+    client: &reqwest::Client,
+    server: &Server,
+    config: &HealthCheckConfig,
+) -> Result<(), String> {
+    // OpenAI-compatible servers have no `/v1/info` endpoint
+    let path = match server.flavor {
+        ServerFlavor::LlamaEdge => config.path.as_str(),
+        ServerFlavor::OpenAI => "/v1/models",
+    };
+    let probe_url = format!(
+        "{}/{}",
+        server.url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(config.timeout),
+        server.authorize(client.get(&probe_url)).send(),
+    )
+    .await
+    .map_err(|_| format!("Timed out after {} seconds", config.timeout))?
+    .map_err(|e| e.to_string())?;
+
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(format!("Unexpected status: {}", response.status())),
+    }
+}
+
+#[test]
+fn test_health_status_transitions() {
+    let config = HealthCheckConfig {
+        unhealthy_threshold: 2,
+        healthy_threshold: 2,
+        ..Default::default()
+    };
+    let mut status = HealthStatus::default();
+    assert!(status.healthy);
+
+    assert_eq!(status.record(Err("refused".to_string()), &config), None);
+    assert_eq!(
+        status.record(Err("refused".to_string()), &config),
+        Some(false)
+    );
+    assert_eq!(status.record(Err("refused".to_string()), &config), None);
+    assert!(!status.healthy);
+    assert_eq!(status.last_error.as_deref(), Some("refused"));
+
+    assert_eq!(status.record(Ok(()), &config), None);
+    assert_eq!(status.record(Ok(()), &config), Some(true));
+    assert!(status.healthy);
+    assert!(status.last_probe.is_some());
+    assert!(status.last_error.is_none());
+}
@@ -1,5 +1,6 @@
-use crate::server::ServerId;
+use crate::server::{Server, ServerId, ServerKind};
 use chat_prompts::PromptTemplateType;
+use endpoints::models::Model;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -34,6 +35,105 @@ pub(crate) struct ApiServer {
     pub(crate) server_id: Option<ServerId>,
 }
 
+impl ApiServer {
+    /// Returns the kinds of the models reported in the server info
+    pub(crate) fn kind(&self) -> ServerKind {
+        let mut kind = ServerKind::empty();
+        kind.set(ServerKind::chat, self.chat_model.is_some());
+        kind.set(ServerKind::embeddings, self.embedding_model.is_some());
+        kind.set(ServerKind::image, self.image_model.is_some());
+        kind.set(ServerKind::tts, self.tts_model.is_some());
+        kind.set(ServerKind::translate, self.translate_model.is_some());
+        kind.set(ServerKind::transcribe, self.transcribe_model.is_some());
+        kind
+    }
+
+    /// Builds the server info of an OpenAI-compatible server from its models and the metadata
+    /// supplied at registration. The first model serves every kind of the server.
+    pub(crate) fn from_openai_models(server: &Server, models: &[Model]) -> Self {
+        let metadata = server.metadata.clone().unwrap_or_default();
+        let model_config = |ty: &str| {
+            models.first().map(|model| ModelConfig {
+                name: model.id.clone(),
+                ty: ty.to_string(),
+                ctx_size: metadata.ctx_size,
+                prompt_template: match ty {
+                    "chat" => metadata.prompt_template,
+                    _ => None,
+                },
+                ..Default::default()
+            })
+        };
+        let model_for = |kind: ServerKind, ty: &str| match server.kind.contains(kind) {
+            true => model_config(ty),
+            false => None,
+        };
+
+        let port = reqwest::Url::parse(&server.url)
+            .ok()
+            .and_then(|url| url.port_or_known_default())
+            .map(|port| port.to_string())
+            .unwrap_or_default();
+
+        Self {
+            ty: "openai".to_string(),
+            version: "unknown".to_string(),
+            plugin_version: None,
+            port,
+            chat_model: model_for(ServerKind::chat, "chat"),
+            embedding_model: model_for(ServerKind::embeddings, "embedding"),
+            image_model: model_for(ServerKind::image, "image"),
+            tts_model: model_for(ServerKind::tts, "tts"),
+            translate_model: model_for(ServerKind::translate, "translate"),
+            transcribe_model: model_for(ServerKind::transcribe, "transcribe"),
+            extras: metadata.extras,
+            server_id: Some(server.id.clone()),
+        }
+    }
+}
+
+/// Returns the fields that differ between two server infos, as `path: old -> new`
+pub(crate) fn diff(old: &ApiServer, new: &ApiServer) -> Vec<String> {
+    fn diff_values(
+        path: &str,
+        old: &serde_json::Value,
+        new: &serde_json::Value,
+        changes: &mut Vec<String>,
+    ) {
+        use serde_json::Value;
+
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                let keys = old
+                    .keys()
+                    .chain(new.keys())
+                    .collect::<std::collections::BTreeSet<_>>();
+                for key in keys {
+                    let path = match path.is_empty() {
+                        true => key.to_string(),
+                        false => format!("{}.{}", path, key),
+                    };
+                    diff_values(
+                        &path,
+                        old.get(key).unwrap_or(&Value::Null),
+                        new.get(key).unwrap_or(&Value::Null),
+                        changes,
+                    );
+                }
+            }
+            (old, new) if old != new => changes.push(format!("{}: {} -> {}", path, old, new)),
+            _ => {}
+        }
+    }
+
+    let mut changes = Vec::new();
+    if let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) {
+        diff_values("", &old, &new, &mut changes);
+    }
+
+    changes
+}
+
 #[derive(Debug, Clone, Default, Deserialize)]
 pub(crate) struct ModelConfig {
     // model name
@@ -204,8 +304,59 @@ fn test_deserialize_api_server() {
     assert_eq!(server.version, "0.2.4");
     assert_eq!(server.plugin_version, Some("Unknown".to_string()));
     assert_eq!(server.port, "12345");
+    assert_eq!(server.kind(), ServerKind::image);
     let image_model = server.image_model.unwrap();
     assert_eq!(image_model.name, "sd-v1.5");
     assert_eq!(image_model.ty, "image");
     assert_eq!(server.extras, HashMap::new());
 }
+
+#[test]
+fn test_diff_api_server() {
+    let old: ApiServer = serde_json::from_str(
+        r#"{"type":"llama","version":"0.16.0","port":"8080","chat_model":{"name":"Llama-3.2-3B","type":"chat","ctx_size":4096},"extras":{}}"#,
+    )
+    .unwrap();
+    let new: ApiServer = serde_json::from_str(
+        r#"{"type":"llama","version":"0.16.1","port":"8080","chat_model":{"name":"Qwen2.5-7B","type":"chat","ctx_size":32768},"extras":{}}"#,
+    )
+    .unwrap();
+
+    assert!(diff(&old, &old).is_empty());
+    assert_eq!(
+        diff(&old, &new),
+        vec![
+            "chat_model.ctx_size: 4096 -> 32768".to_string(),
+            r#"chat_model.name: "Llama-3.2-3B" -> "Qwen2.5-7B""#.to_string(),
+            r#"version: "0.16.0" -> "0.16.1""#.to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_api_server_from_openai_models() {
+    let mut server: Server = serde_json::from_str(
+        r#"{"url": "http://localhost:8000/", "kind": "chat", "flavor": "openai", "metadata": {"ctx_size": 8192}}"#,
+    )
+    .unwrap();
+    let models: Vec<Model> = serde_json::from_str(
+        r#"[{"id": "Qwen2.5-7B-Instruct", "created": 0, "object": "model", "owned_by": "vllm"}]"#,
+    )
+    .unwrap();
+
+    let api_server = ApiServer::from_openai_models(&server, &models);
+    assert_eq!(api_server.port, "8000");
+    assert_eq!(api_server.server_id.as_ref(), Some(&server.id));
+    let chat_model = api_server.chat_model.unwrap();
+    assert_eq!(chat_model.name, "Qwen2.5-7B-Instruct");
+    assert_eq!(chat_model.ty, "chat");
+    assert_eq!(chat_model.ctx_size, Some(8192));
+    assert!(api_server.embedding_model.is_none());
+
+    server.url = "https://api.example.com".to_string();
+    server.kind = ServerKind::embeddings;
+    let api_server = ApiServer::from_openai_models(&server, &models);
+    assert_eq!(api_server.port, "443");
+    assert!(api_server.chat_model.is_none());
+    assert_eq!(api_server.embedding_model.unwrap().ty, "embedding");
+}
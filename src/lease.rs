@@ -0,0 +1,89 @@
+use crate::{dual_info, dual_warn, registry, server::ServerId, AppState};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Lease of a server registered with a TTL
+#[derive(Debug, Clone)]
+pub(crate) struct Lease {
+    ttl: Duration,
+    expires_at: Instant,
+}
+impl Lease {
+    pub(crate) fn new(ttl_seconds: u64) -> Self {
+        let ttl = Duration::from_secs(ttl_seconds);
+        Self {
+            ttl,
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    /// Extends the lease by another TTL from now
+    pub(crate) fn renew(&mut self) {
+        self.expires_at = Instant::now() + self.ttl;
+    }
+
+    /// Returns the time left until the lease expires
+    pub(crate) fn remaining(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
+/// Periodically unregisters the servers whose lease has expired
+pub(crate) async fn run_lease_sweeper(state: Arc<AppState>) {
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+
+        let expired = state
+            .leases
+            .read()
+            .await
+            .iter()
+            .filter(|(_, lease)| lease.is_expired())
+            .map(|(server_id, _)| server_id.clone())
+            .collect::<Vec<ServerId>>();
+        if expired.is_empty() {
+            continue;
+        }
+
+        for server_id in expired.iter() {
+            dual_warn!(
+                "The lease of server {} expired without a heartbeat",
+                server_id
+            );
+
+            match state.unregister_downstream_server(server_id).await {
+                Ok(()) => dual_info!("Unregistered expired server {}", server_id),
+                Err(e) => {
+                    dual_warn!("Failed to unregister expired server {}: {}", server_id, e);
+                    state.leases.write().await.remove(server_id);
+                }
+            }
+        }
+
+        // persist the registry
+        let _ = registry::save(&state).await;
+    }
+}
+
+#[test]
+fn test_lease_renew() {
+    let mut lease = Lease::new(60);
+    assert!(!lease.is_expired());
+    assert!(lease.remaining() <= Duration::from_secs(60));
+
+    lease.expires_at = Instant::now();
+    assert!(lease.is_expired());
+    assert_eq!(lease.remaining(), Duration::ZERO);
+
+    lease.renew();
+    assert!(!lease.is_expired());
+    assert!(lease.remaining() > Duration::from_secs(59));
+}
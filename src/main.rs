@@ -1,33 +1,49 @@
 #[macro_use]
 extern crate log;
 
+mod affinity;
+mod canary;
+mod circuit_breaker;
 mod config;
+mod context;
+mod drain;
 mod error;
 mod handler;
+mod health;
 mod info;
+mod lease;
+mod queue;
 mod rag;
+mod refresh;
+mod registry;
+mod routing;
 mod server;
 mod utils;
 
+use affinity::AffinityTable;
 use anyhow::Result;
 use axum::{
     body::Body,
     http::{self, HeaderValue, Request},
     middleware,
-    routing::{get, post},
+    routing::{get, patch, post},
     Router,
 };
+use canary::{ArmStatus, CanaryArm, CanaryStats, CanaryStatus, CanaryUpdate};
 use clap::Parser;
-use config::Config;
+use config::{Config, ModelAlias};
 use error::{ServerError, ServerResult};
 use futures_util::StreamExt;
+use health::HealthStatus;
 use info::ServerInfo;
-use server::{Server, ServerGroup, ServerId, ServerKind};
+use lease::Lease;
+use queue::QueueStatus;
+use routing::RoutingStrategy;
+use server::{Server, ServerGroup, ServerId, ServerKind, ServerStatus, ServerUpdate};
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, HashSet},
     net::{IpAddr, SocketAddr},
     path::PathBuf,
-    str::FromStr,
     sync::Arc,
 };
 use tokio::{net::TcpListener, sync::RwLock};
@@ -88,11 +104,33 @@ async fn main() -> Result<(), ServerError> {
         config.server.port,
     ));
 
+    let enable_health_check = config.health_check.enable;
+    let enable_refresh = config.refresh.enable;
+
     let app_state = Arc::new(AppState::new(config, ServerInfo::default()));
 
+    // register the servers declared in the config file
+    registry::register_static_servers(app_state.clone()).await;
+
+    // restore the servers persisted in the state file before the admin API can change them
+    registry::restore(app_state.clone()).await;
+
+    // unregister the servers whose lease expired
+    tokio::spawn(lease::run_lease_sweeper(app_state.clone()));
+
+    // probe the registered servers in the background
+    if enable_health_check {
+        tokio::spawn(health::run_health_checker(app_state.clone()));
+    }
+
+    // keep the server info and the model lists up to date
+    if enable_refresh {
+        tokio::spawn(refresh::run_refresher(app_state.clone()));
+    }
+
     // Set up CORS
     let cors = CorsLayer::new()
-        .allow_methods([http::Method::GET, http::Method::POST])
+        .allow_methods([http::Method::GET, http::Method::POST, http::Method::PATCH])
         .allow_headers(Any)
         .allow_origin(Any);
 
@@ -127,6 +165,44 @@ async fn main() -> Result<(), ServerError> {
             "/admin/servers",
             get(handler::admin::list_downstream_servers_handler),
         )
+        .route(
+            "/admin/servers/export",
+            get(handler::admin::export_servers_handler),
+        )
+        .route(
+            "/admin/servers/import",
+            post(handler::admin::import_servers_handler),
+        )
+        .route(
+            "/admin/servers/:id",
+            patch(handler::admin::update_downstream_server_handler),
+        )
+        .route(
+            "/admin/servers/:id/heartbeat",
+            post(handler::admin::heartbeat_handler),
+        )
+        .route(
+            "/admin/servers/:id/refresh",
+            post(handler::admin::refresh_server_handler),
+        )
+        .route(
+            "/admin/servers/:id/drain",
+            post(handler::admin::drain_handler),
+        )
+        .route(
+            "/admin/servers/:id/undrain",
+            post(handler::admin::undrain_handler),
+        )
+        .route("/admin/queues", get(handler::admin::get_queues_handler))
+        .route(
+            "/admin/canaries",
+            get(handler::admin::get_canaries_handler).post(handler::admin::set_canary_handler),
+        )
+        .route(
+            "/admin/routing",
+            get(handler::admin::get_routing_strategy_handler)
+                .post(handler::admin::set_routing_strategy_handler),
+        )
         .layer(cors)
         .layer(TraceLayer::new_for_http())
         .layer(middleware::from_fn(
@@ -176,27 +252,104 @@ async fn main() -> Result<(), ServerError> {
 struct AppState {
     config: Arc<RwLock<Config>>,
     server_group: Arc<RwLock<HashMap<ServerKind, ServerGroup>>>,
+    /// Registered servers by id
+    servers: Arc<RwLock<HashMap<ServerId, Server>>>,
     server_info: Arc<RwLock<ServerInfo>>,
     models: Arc<RwLock<HashMap<ServerId, Vec<endpoints::models::Model>>>>,
+    health: Arc<RwLock<HashMap<ServerId, HealthStatus>>>,
+    leases: Arc<RwLock<HashMap<ServerId, Lease>>>,
+    /// Serializes the writes of the state file
+    registry_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Restored servers that failed verification, kept in the state file while their
+    /// registration is retried
+    pending_restores: Arc<RwLock<HashMap<ServerId, registry::ServerRecord>>>,
+    /// Servers the chat sessions are pinned to
+    affinity: Arc<AffinityTable>,
+    /// Counters of the arms of the traffic splits of the model aliases
+    canaries: Arc<CanaryStats>,
 }
 
 impl AppState {
     fn new(config: Config, server_info: ServerInfo) -> Self {
         Self {
             server_group: Arc::new(RwLock::new(HashMap::new())),
+            servers: Arc::new(RwLock::new(HashMap::new())),
             config: Arc::new(RwLock::new(config)),
             server_info: Arc::new(RwLock::new(server_info)),
             models: Arc::new(RwLock::new(HashMap::new())),
+            health: Arc::new(RwLock::new(HashMap::new())),
+            leases: Arc::new(RwLock::new(HashMap::new())),
+            registry_lock: Arc::new(tokio::sync::Mutex::new(())),
+            pending_restores: Arc::new(RwLock::new(HashMap::new())),
+            affinity: Arc::new(AffinityTable::default()),
+            canaries: Arc::new(CanaryStats::default()),
         }
     }
 
     pub async fn register_downstream_server(&self, server: Server) -> ServerResult<()> {
+        self.add_downstream_server(server, None).await
+    }
+
+    /// Registers a server in place of the server with the given id, which may have the same url.
+    /// The replaced server is only unregistered once the new one is registered, so it keeps
+    /// serving if the registration fails.
+    pub(crate) async fn replace_downstream_server(
+        &self,
+        replaced_id: impl AsRef<str>,
+        server: Server,
+    ) -> ServerResult<()> {
+        let replaced_id = replaced_id.as_ref();
+        self.add_downstream_server(server, Some(replaced_id))
+            .await?;
+
+        // the replaced server may have been unregistered concurrently
+        if let Err(e) = self.unregister_downstream_server(replaced_id).await {
+            dual_warn!(
+                "Failed to unregister the replaced server {}: {}",
+                replaced_id,
+                e
+            );
+        }
+
+        Ok(())
+    }
+
+    // Register a server, allowing it to share its url with the server it replaces
+    async fn add_downstream_server(
+        &self,
+        server: Server,
+        replaced_id: Option<&str>,
+    ) -> ServerResult<()> {
+        let routing = self.config.read().await.routing.clone();
+
+        // reject a server whose id or url is already registered
+        let mut servers = self.servers.write().await;
+        if servers.contains_key(&server.id) {
+            let err_msg = format!("Server already registered: {}", server.id);
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::Operation(err_msg));
+        }
+        let others = servers
+            .values()
+            .filter(|existing| Some(existing.id.as_str()) != replaced_id);
+        if let Some(existing) = find_by_url(others, &server.url) {
+            let err_msg = format!(
+                "The url {} is already registered by server {}",
+                server.url, existing.id
+            );
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::DuplicateServer(err_msg));
+        }
+
         if server.kind.contains(ServerKind::chat) {
             self.server_group
                 .write()
                 .await
                 .entry(ServerKind::chat)
-                .or_insert(ServerGroup::new(ServerKind::chat))
+                .or_insert(ServerGroup::new(
+                    ServerKind::chat,
+                    routing.strategy_for(ServerKind::chat),
+                ))
                 .register(server.clone())
                 .await?;
         }
@@ -205,7 +358,10 @@ impl AppState {
                 .write()
                 .await
                 .entry(ServerKind::embeddings)
-                .or_insert(ServerGroup::new(ServerKind::embeddings))
+                .or_insert(ServerGroup::new(
+                    ServerKind::embeddings,
+                    routing.strategy_for(ServerKind::embeddings),
+                ))
                 .register(server.clone())
                 .await?;
         }
@@ -214,7 +370,10 @@ impl AppState {
                 .write()
                 .await
                 .entry(ServerKind::image)
-                .or_insert(ServerGroup::new(ServerKind::image))
+                .or_insert(ServerGroup::new(
+                    ServerKind::image,
+                    routing.strategy_for(ServerKind::image),
+                ))
                 .register(server.clone())
                 .await?;
         }
@@ -223,7 +382,10 @@ impl AppState {
                 .write()
                 .await
                 .entry(ServerKind::tts)
-                .or_insert(ServerGroup::new(ServerKind::tts))
+                .or_insert(ServerGroup::new(
+                    ServerKind::tts,
+                    routing.strategy_for(ServerKind::tts),
+                ))
                 .register(server.clone())
                 .await?;
         }
@@ -232,7 +394,10 @@ impl AppState {
                 .write()
                 .await
                 .entry(ServerKind::translate)
-                .or_insert(ServerGroup::new(ServerKind::translate))
+                .or_insert(ServerGroup::new(
+                    ServerKind::translate,
+                    routing.strategy_for(ServerKind::translate),
+                ))
                 .register(server.clone())
                 .await?;
         }
@@ -241,11 +406,30 @@ impl AppState {
                 .write()
                 .await
                 .entry(ServerKind::transcribe)
-                .or_insert(ServerGroup::new(ServerKind::transcribe))
+                .or_insert(ServerGroup::new(
+                    ServerKind::transcribe,
+                    routing.strategy_for(ServerKind::transcribe),
+                ))
                 .register(server.clone())
                 .await?;
         }
 
+        servers.insert(server.id.clone(), server.clone());
+
+        // a newly registered server is considered healthy until probes say otherwise
+        self.health
+            .write()
+            .await
+            .insert(server.id.clone(), HealthStatus::default());
+
+        // start the lease of a server registered with a TTL
+        if let Some(ttl_seconds) = server.ttl_seconds {
+            self.leases
+                .write()
+                .await
+                .insert(server.id.clone(), Lease::new(ttl_seconds));
+        }
+
         Ok(())
     }
 
@@ -253,58 +437,381 @@ impl AppState {
         &self,
         server_id: impl AsRef<str>,
     ) -> ServerResult<()> {
-        let mut found = false;
+        let server_id = server_id.as_ref();
+
+        let server = match self.servers.write().await.remove(server_id) {
+            Some(server) => server,
+            None => {
+                // a restored server whose registration is still retried stops being retried
+                if self
+                    .pending_restores
+                    .write()
+                    .await
+                    .remove(server_id)
+                    .is_some()
+                {
+                    dual_info!("Cancelled the pending registration of server {}", server_id);
+                    return Ok(());
+                }
 
-        // unregister the server from the servers
-        {
-            // parse server kind from server id
-            let kinds = server_id
-                .as_ref()
-                .split("-server-")
-                .next()
-                .unwrap()
-                .split("-")
-                .collect::<Vec<&str>>();
+                let err = ServerError::NotFoundServerId(server_id.to_string());
+                dual_error!("{}", err);
+                return Err(err);
+            }
+        };
 
+        // unregister the server from the groups it belongs to
+        {
             let group_map = self.server_group.read().await;
 
-            for kind in kinds {
-                let kind = ServerKind::from_str(kind).unwrap();
+            for kind in server.kind.iter() {
                 if let Some(group) = group_map.get(&kind) {
-                    group.unregister(server_id.as_ref()).await?;
-                    dual_info!("Unregistered {} server: {}", &kind, server_id.as_ref());
+                    group.unregister(server_id).await?;
+                    dual_info!("Unregistered {} server: {}", kind, server_id);
+                }
+            }
+        }
+
+        // remove the server info from the server_info
+        let mut server_info = self.server_info.write().await;
+        server_info.servers.remove(server_id);
+
+        // remove the server from the models
+        let mut models = self.models.write().await;
+        models.remove(server_id);
+
+        // remove the health state of the server
+        let mut health = self.health.write().await;
+        health.remove(server_id);
+
+        // remove the lease of the server
+        let mut leases = self.leases.write().await;
+        leases.remove(server_id);
+
+        Ok(())
+    }
+
+    /// Updates a registered server in place, keeping its id. If its kind changes, the server
+    /// is moved between the server groups accordingly.
+    pub(crate) async fn update_downstream_server(
+        &self,
+        server_id: impl AsRef<str>,
+        update: &ServerUpdate,
+    ) -> ServerResult<Server> {
+        let server_id = server_id.as_ref();
+
+        let mut servers = self.servers.write().await;
+        let current = match servers.get(server_id) {
+            Some(server) => server.clone(),
+            None => return Err(ServerError::NotFoundServerId(server_id.to_string())),
+        };
+
+        let mut updated = current.clone();
+        update.apply(&mut updated);
+
+        // reject a url that is already registered by another server
+        let others = servers.values().filter(|server| server.id != server_id);
+        if let Some(existing) = find_by_url(others, &updated.url) {
+            let err_msg = format!(
+                "The url {} is already registered by server {}",
+                updated.url, existing.id
+            );
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::DuplicateServer(err_msg));
+        }
+
+        let removed_kinds = current.kind - updated.kind;
+        let added_kinds = updated.kind - current.kind;
+
+        let routing = self.config.read().await.routing.clone();
+        let mut group_map = self.server_group.write().await;
+
+        // leave the groups of the kinds the server no longer serves
+        for kind in removed_kinds.iter() {
+            if let Some(group) = group_map.get(&kind) {
+                group.unregister(server_id).await?;
+                dual_info!("Moved server {} out of the {} servers", server_id, kind);
+            }
+        }
+
+        // update the server in the groups it stays in
+        for kind in (current.kind & updated.kind).iter() {
+            if let Some(group) = group_map.get(&kind) {
+                group
+                    .update_server(server_id, |server| update.apply(server))
+                    .await;
+            }
+        }
+
+        // join the groups of the kinds the server newly serves. The clone shares the circuit
+        // breaker and the draining state of the server, but the health has to be carried over.
+        let healthy = self
+            .health
+            .read()
+            .await
+            .get(server_id)
+            .is_none_or(|health| health.healthy);
+        for kind in added_kinds.iter() {
+            let group = group_map
+                .entry(kind)
+                .or_insert(ServerGroup::new(kind, routing.strategy_for(kind)));
+            group.register(updated.clone()).await?;
+            if !healthy {
+                group.set_healthy(server_id, false).await;
+            }
+            dual_info!("Moved server {} into the {} servers", server_id, kind);
+        }
 
-                    if !found {
-                        found = true;
+        servers.insert(server_id.to_string(), updated.clone());
+
+        Ok(updated)
+    }
+
+    /// Returns the routing strategy in effect for each server kind
+    pub(crate) async fn routing_strategies(&self) -> HashMap<ServerKind, RoutingStrategy> {
+        let routing = self.config.read().await.routing.clone();
+        let groups = self.server_group.read().await;
+
+        let mut strategies = HashMap::new();
+        for kind in ServerKind::all().iter() {
+            let strategy = match groups.get(&kind) {
+                Some(group) => group.strategy().await,
+                None => routing.strategy_for(kind),
+            };
+            strategies.insert(kind, strategy);
+        }
+
+        strategies
+    }
+
+    /// Switches the routing strategy of the given server kind(s) at runtime
+    pub(crate) async fn set_routing_strategy(&self, kind: ServerKind, strategy: RoutingStrategy) {
+        let mut config = self.config.write().await;
+        let groups = self.server_group.read().await;
+
+        for kind in kind.iter() {
+            config.routing.strategy.insert(kind, strategy);
+            if let Some(group) = groups.get(&kind) {
+                group.set_strategy(strategy).await;
+            }
+
+            dual_info!("Routing strategy of {} servers: {}", kind, strategy);
+        }
+    }
+
+    /// Returns the ids of the servers of the given kind that serve the given model
+    pub(crate) async fn servers_for_model(
+        &self,
+        kind: ServerKind,
+        model: impl AsRef<str>,
+    ) -> ServerResult<HashSet<ServerId>> {
+        let model = model.as_ref();
+
+        let server_ids = match self.server_group.read().await.get(&kind) {
+            Some(group) => group.server_ids().await,
+            None => Vec::new(),
+        };
+
+        let models = self.models.read().await;
+        let mut matched = HashSet::new();
+        let mut available = BTreeSet::new();
+        for server_id in server_ids {
+            if let Some(server_models) = models.get(&server_id) {
+                for server_model in server_models {
+                    if server_model.id == model {
+                        matched.insert(server_id.clone());
                     }
+                    available.insert(server_model.id.as_str());
                 }
             }
         }
 
-        if found {
-            // remove the server info from the server_info
-            let mut server_info = self.server_info.write().await;
-            server_info.servers.remove(server_id.as_ref());
+        if matched.is_empty() {
+            let err_msg = format!(
+                "The model `{}` is not served by any {} server. Available models: [{}]",
+                model,
+                kind,
+                available.into_iter().collect::<Vec<_>>().join(", ")
+            );
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::NotFoundModel(err_msg));
+        }
+
+        Ok(matched)
+    }
+
+    /// Returns every registered server once, regardless of how many kinds it serves
+    pub(crate) async fn downstream_servers(&self) -> Vec<Server> {
+        let mut servers = self
+            .servers
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        servers.sort_by(|a, b| a.id.cmp(&b.id));
+
+        servers
+    }
+
+    /// Returns the registered server with the given url, if any
+    pub(crate) async fn find_server_by_url(&self, url: impl AsRef<str>) -> Option<Server> {
+        find_by_url(self.servers.read().await.values(), url.as_ref()).cloned()
+    }
+
+    /// Updates the health state of a server in every group it belongs to
+    pub(crate) async fn set_server_health(&self, server_id: impl AsRef<str>, healthy: bool) {
+        let server_id = server_id.as_ref();
+
+        for group in self.server_group.read().await.values() {
+            group.set_healthy(server_id, healthy).await;
+        }
+
+        match healthy {
+            true => dual_info!("Server {} is healthy again", server_id),
+            false => dual_warn!("Server {} is unhealthy", server_id),
+        }
+    }
+
+    /// Returns the registered server with the given id
+    pub(crate) async fn get_server(&self, server_id: impl AsRef<str>) -> ServerResult<Server> {
+        let server_id = server_id.as_ref();
+        self.servers
+            .read()
+            .await
+            .get(server_id)
+            .cloned()
+            .ok_or_else(|| ServerError::NotFoundServerId(server_id.to_string()))
+    }
 
-            // remove the server from the models
-            let mut models = self.models.write().await;
-            models.remove(server_id.as_ref());
+    /// Renews the lease of a server. Returns the TTL of the lease.
+    pub(crate) async fn renew_lease(&self, server_id: impl AsRef<str>) -> ServerResult<u64> {
+        let server_id = server_id.as_ref();
+
+        if let Some(lease) = self.leases.write().await.get_mut(server_id) {
+            lease.renew();
+            return Ok(lease.remaining().as_secs());
         }
 
-        if !found {
-            return Err(ServerError::Operation(format!(
-                "Server {} not found",
-                server_id.as_ref()
-            )));
+        match self
+            .downstream_servers()
+            .await
+            .iter()
+            .any(|s| s.id == server_id)
+        {
+            true => Err(ServerError::BadRequest(format!(
+                "Server {} was registered without `ttl_seconds`",
+                server_id
+            ))),
+            false => Err(ServerError::NotFoundServerId(server_id.to_string())),
         }
+    }
 
-        Ok(())
+    /// Returns the wait queue of every server group
+    pub(crate) async fn queue_statuses(&self) -> HashMap<ServerKind, QueueStatus> {
+        let max_size = self.config.read().await.queue.max_size;
+
+        self.server_group
+            .read()
+            .await
+            .iter()
+            .map(|(kind, group)| {
+                let status = QueueStatus {
+                    depth: group.queue().depth(),
+                    max_size,
+                };
+                (*kind, status)
+            })
+            .collect()
+    }
+
+    /// Returns the traffic splits of the model aliases with a canary model
+    pub(crate) async fn canary_statuses(&self) -> Vec<CanaryStatus> {
+        self.config
+            .read()
+            .await
+            .models
+            .iter()
+            .filter_map(|alias| self.canary_status(alias))
+            .collect()
+    }
+
+    /// Adjusts the traffic split of a model alias at runtime. Replacing the canary model resets
+    /// the counters of the split.
+    pub(crate) async fn set_canary(&self, update: CanaryUpdate) -> ServerResult<CanaryStatus> {
+        let mut config = self.config.write().await;
+        if let Some(canary) = update
+            .canary
+            .as_ref()
+            .filter(|canary| config.model_alias(canary).is_some())
+        {
+            let err_msg = format!("The canary model `{}` is the name of a model alias", canary);
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::BadRequest(err_msg));
+        }
+
+        let alias = match config.model_alias_mut(&update.name) {
+            Some(alias) => alias,
+            None => {
+                let err_msg = format!("No model alias named `{}`", update.name);
+                dual_error!("{}", &err_msg);
+                return Err(ServerError::NotFoundModel(err_msg));
+            }
+        };
+
+        if let Some(canary) = update.canary {
+            if alias.canary.as_ref() != Some(&canary) {
+                self.canaries.reset(&alias.name);
+            }
+            alias.canary = Some(canary);
+        }
+
+        let canary = match &alias.canary {
+            Some(canary) => canary.clone(),
+            None => {
+                let err_msg = format!("The model alias `{}` has no canary model", update.name);
+                dual_error!("{}", &err_msg);
+                return Err(ServerError::BadRequest(err_msg));
+            }
+        };
+        alias.canary_percent = update.canary_percent;
+
+        dual_info!(
+            "Traffic split of the model alias {}: {}% to the canary model {}, the rest to the model {}",
+            alias.name,
+            alias.canary_percent,
+            canary,
+            alias.model
+        );
+
+        // the alias has a canary model at this point
+        Ok(self.canary_status(alias).unwrap())
+    }
+
+    // Traffic split of a model alias, if it has a canary model
+    fn canary_status(&self, alias: &ModelAlias) -> Option<CanaryStatus> {
+        let canary = alias.canary.clone()?;
+
+        Some(CanaryStatus {
+            name: alias.name.clone(),
+            canary_percent: alias.canary_percent,
+            stable: ArmStatus {
+                model: alias.model.clone(),
+                counters: self.canaries.get(&alias.name, CanaryArm::Stable),
+            },
+            canary: ArmStatus {
+                model: canary,
+                counters: self.canaries.get(&alias.name, CanaryArm::Canary),
+            },
+        })
     }
 
     pub(crate) async fn list_downstream_servers(
         &self,
-    ) -> ServerResult<HashMap<ServerKind, Vec<crate::server::Server>>> {
+    ) -> ServerResult<HashMap<ServerKind, Vec<ServerStatus>>> {
         let servers = self.server_group.read().await;
+        let health = self.health.read().await;
+        let leases = self.leases.read().await;
 
         let mut server_groups = HashMap::new();
         for (kind, group) in servers.iter() {
@@ -313,9 +820,14 @@ impl AppState {
 
                 // Create a new Vec with cloned Server instances using async stream
                 let server_vec = futures_util::stream::iter(servers.iter())
-                    .then(|server_lock| async move {
+                    .then(|server_lock| async {
                         let server = server_lock.read().await;
-                        server.clone()
+                        let mut status = ServerStatus::from(&*server);
+                        status.health = health.get(&server.id).cloned();
+                        status.lease_expires_in = leases
+                            .get(&server.id)
+                            .map(|lease| lease.remaining().as_secs());
+                        status
                     })
                     .collect::<Vec<_>>()
                     .await;
@@ -324,6 +836,98 @@ impl AppState {
             }
         }
 
+        // list the restored servers whose registration is still retried
+        for record in self.pending_restores.read().await.values() {
+            let mut status = ServerStatus::from(&Server::from(record.clone()));
+            status.pending = true;
+            for kind in record.kind.iter() {
+                server_groups
+                    .entry(kind)
+                    .or_insert_with(Vec::new)
+                    .push(status.clone());
+            }
+        }
+
         Ok(server_groups)
     }
 }
+
+// Find the server registered with the given url, ignoring a trailing slash
+fn find_by_url<'a>(mut servers: impl Iterator<Item = &'a Server>, url: &str) -> Option<&'a Server> {
+    let url = url.trim_end_matches('/');
+    servers.find(|server| server.url.trim_end_matches('/') == url)
+}
+
+#[tokio::test]
+async fn test_replace_downstream_server() {
+    let state = AppState::new(Config::default(), ServerInfo::default());
+    let server = |id: &str, url: &str| Server::new(id.to_string(), url, ServerKind::chat);
+
+    state
+        .register_downstream_server(server("server-1", "http://localhost:8000"))
+        .await
+        .unwrap();
+    state
+        .register_downstream_server(server("server-2", "http://localhost:8001"))
+        .await
+        .unwrap();
+
+    // a failed registration keeps the replaced server
+    assert!(state
+        .replace_downstream_server("server-1", server("server-2", "http://localhost:8000"))
+        .await
+        .is_err());
+    assert!(state.get_server("server-1").await.is_ok());
+
+    // the replacement may reuse the url of the replaced server, but not of another one
+    assert!(matches!(
+        state
+            .replace_downstream_server("server-1", server("server-3", "http://localhost:8001"))
+            .await,
+        Err(ServerError::DuplicateServer(_))
+    ));
+    state
+        .replace_downstream_server("server-1", server("server-3", "http://localhost:8000"))
+        .await
+        .unwrap();
+    assert!(state.get_server("server-1").await.is_err());
+    assert_eq!(
+        state.get_server("server-3").await.unwrap().url,
+        "http://localhost:8000"
+    );
+}
+
+#[tokio::test]
+async fn test_update_keeps_server_state() {
+    let state = AppState::new(Config::default(), ServerInfo::default());
+    state
+        .register_downstream_server(Server::new(
+            "server-1".to_string(),
+            "http://localhost:8000",
+            ServerKind::chat,
+        ))
+        .await
+        .unwrap();
+
+    // the server is down
+    state
+        .health
+        .write()
+        .await
+        .get_mut("server-1")
+        .unwrap()
+        .healthy = false;
+    state.set_server_health("server-1", false).await;
+
+    let update: ServerUpdate = serde_json::from_str(r#"{"kind": "chat,embeddings"}"#).unwrap();
+    state
+        .update_downstream_server("server-1", &update)
+        .await
+        .unwrap();
+
+    // it stays unavailable in the group it moved into
+    let groups = state.server_group.read().await;
+    let filter = server::ServerFilter::default();
+    assert!(!groups[&ServerKind::chat].is_available(&filter).await);
+    assert!(!groups[&ServerKind::embeddings].is_available(&filter).await);
+}
@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// Requests of a server group waiting for a saturated server to free an in-flight slot
+#[derive(Debug, Default)]
+pub(crate) struct WaitQueue {
+    /// Number of requests waiting
+    depth: AtomicUsize,
+    /// Notified whenever a server of the group releases a slot
+    released: Notify,
+}
+impl WaitQueue {
+    /// Upper bound of a single wait. A slot released by a request of another group, which does
+    /// not notify this queue, is noticed after this interval at the latest.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Returns the number of requests waiting
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Joins the queue unless `max_size` requests are already waiting. The request leaves the
+    /// queue when the returned ticket is dropped.
+    pub(crate) fn join(self: &Arc<Self>, max_size: usize) -> Option<QueueTicket> {
+        self.depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                (depth < max_size).then_some(depth + 1)
+            })
+            .ok()?;
+
+        Some(QueueTicket {
+            queue: self.clone(),
+        })
+    }
+
+    /// Waits until a slot is released or the timeout passes
+    pub(crate) async fn wait(&self, timeout: Duration) {
+        let _ =
+            tokio::time::timeout(timeout.min(Self::POLL_INTERVAL), self.released.notified()).await;
+    }
+
+    /// Wakes the waiting requests to compete for a released slot
+    pub(crate) fn notify(&self) {
+        self.released.notify_waiters();
+    }
+}
+
+/// Place of a request in a wait queue, released on drop
+#[derive(Debug)]
+pub(crate) struct QueueTicket {
+    queue: Arc<WaitQueue>,
+}
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.queue.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of the wait queue of a server group reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct QueueStatus {
+    /// Number of requests waiting
+    pub(crate) depth: usize,
+    /// Maximum number of requests that may wait
+    pub(crate) max_size: usize,
+}
+
+#[test]
+fn test_wait_queue_bound() {
+    let queue = Arc::new(WaitQueue::default());
+
+    let first = queue.join(2).unwrap();
+    let second = queue.join(2).unwrap();
+    assert_eq!(queue.depth(), 2);
+    assert!(queue.join(2).is_none());
+
+    drop(first);
+    assert_eq!(queue.depth(), 1);
+    let _third = queue.join(2).unwrap();
+    drop(second);
+    assert_eq!(queue.depth(), 1);
+}
@@ -1,6 +1,7 @@
 use crate::{
     dual_debug, dual_error, dual_info, dual_warn,
     error::{ServerError, ServerResult},
+    handler::ModelRoute,
     AppState,
 };
 use axum::{
@@ -8,7 +9,9 @@ use axum::{
     extract::{Json, State},
     http::{HeaderMap, Response},
 };
-use chat_prompts::{error as ChatPromptsError, MergeRagContext, MergeRagContextPolicy};
+use chat_prompts::{
+    error as ChatPromptsError, MergeRagContext, MergeRagContextPolicy, PromptTemplateType,
+};
 use endpoints::{
     chat::{ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionUserMessageContent},
     embeddings::{EmbeddingObject, EmbeddingRequest, EmbeddingsResponse, InputText},
@@ -33,6 +36,11 @@ pub async fn chat(
 
     dual_info!("Received a new chat request - request_id: {}", request_id);
 
+    // resolve the model of the request once, so that the prompt template is read from the
+    // servers the request is forwarded to
+    let route =
+        crate::handler::chat_route(&state, &headers, &mut chat_request, &request_id).await?;
+
     // qdrant config
     let qdrant_config_vec =
         match get_qdrant_configs(State(state.clone()), &chat_request, &request_id).await {
@@ -107,25 +115,8 @@ pub async fn chat(
             return Err(ServerError::BadRequest(err_msg.to_string()));
         }
 
-        // get the prompt template from the chat server
-        let prompt_template = {
-            let server_info = state.server_info.read().await;
-            let chat_server = server_info
-                .servers
-                .iter()
-                .find(|(_server_id, server)| server.chat_model.is_some());
-            match chat_server {
-                Some((_server_id, chat_server)) => {
-                    let chat_model = chat_server.chat_model.as_ref().unwrap();
-                    chat_model.prompt_template.unwrap()
-                }
-                None => {
-                    let err_msg = "No chat server available";
-                    dual_error!("{} - request_id: {}", err_msg, request_id);
-                    return Err(ServerError::Operation(err_msg.to_string()));
-                }
-            }
-        };
+        // get the prompt template from a chat server the request is routed to
+        let prompt_template = chat_prompt_template(&state, &route, &request_id).await?;
 
         // get the rag policy
         let (rag_policy, rag_prompt) = {
@@ -154,7 +145,50 @@ pub async fn chat(
     }
 
     // perform chat completion
-    crate::handler::chat(State(state.clone()), headers, Json(chat_request)).await
+    crate::handler::forward_chat(state, headers, chat_request, route, request_id).await
+}
+
+// Get the prompt template of a chat server of the route of a request. Servers that do not report
+// a prompt template, e.g. OpenAI-compatible servers, are skipped.
+async fn chat_prompt_template(
+    state: &AppState,
+    route: &ModelRoute,
+    request_id: &str,
+) -> ServerResult<PromptTemplateType> {
+    let servers = state.servers.read().await;
+    let server_info = state.server_info.read().await;
+    let mut chat_servers = server_info
+        .servers
+        .iter()
+        .filter(|(server_id, _)| {
+            servers
+                .get(*server_id)
+                .is_some_and(|server| route.filter.allows(server))
+        })
+        .filter_map(|(server_id, server)| Some((server_id, server.chat_model.as_ref()?)))
+        .collect::<Vec<_>>();
+    chat_servers.sort_by(|a, b| a.0.cmp(b.0));
+
+    if chat_servers.is_empty() {
+        let err_msg = "No chat server available";
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg.to_string()));
+    }
+
+    match chat_servers
+        .iter()
+        .find_map(|(_, chat_model)| chat_model.prompt_template)
+    {
+        Some(prompt_template) => Ok(prompt_template),
+        None => {
+            let err_msg = format!(
+                "No chat server serving the model {} reports a prompt template, which is required to merge the RAG context. Set `metadata.prompt_template` of OpenAI-compatible servers.",
+                route.model.as_deref().unwrap_or("of the request")
+            );
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            Err(ServerError::Operation(err_msg))
+        }
+    }
 }
 
 async fn get_qdrant_configs(
@@ -414,10 +448,14 @@ async fn retrieve_context_with_single_qdrant_config(
                 vdb_api_key: None,
             };
 
+            // the label selector of the chat request does not apply to the embedding servers
+            let mut embedding_headers = headers.clone();
+            embedding_headers.remove(crate::handler::SELECTOR_HEADER);
+
             // compute embeddings for query
             let response = crate::handler::embeddings_handler(
                 State(state.clone()),
-                headers.clone(),
+                embedding_headers,
                 Json(embedding_request),
             )
             .await?;
@@ -910,3 +948,69 @@ pub(crate) async fn qdrant_persist_embeddings(
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_prompt_template_of_alias_fallback() {
+    use crate::{config::ModelAlias, info::ApiServer, server::Server};
+
+    let config = crate::config::Config {
+        models: vec![ModelAlias {
+            name: "gpt-4o-mini".to_string(),
+            model: "Qwen2.5-72B".to_string(),
+            fallbacks: vec!["Llama-3.2-3B".to_string()],
+            canary: None,
+            canary_percent: 0,
+        }],
+        ..Default::default()
+    };
+    let state = AppState::new(config, Default::default());
+
+    // no server serves the primary model of the alias, only its fallback
+    for (url, model, prompt_template) in [
+        (
+            "http://localhost:8000",
+            "Llama-3.2-3B",
+            Some(PromptTemplateType::Llama3Chat),
+        ),
+        ("http://localhost:8001", "Qwen2.5-7B", None),
+    ] {
+        let server: Server =
+            serde_json::from_str(&format!(r#"{{"url": "{}", "kind": "chat"}}"#, url)).unwrap();
+        let mut api_server: ApiServer = serde_json::from_str(&format!(
+            r#"{{"type":"llama","version":"0.16.0","port":"8080","chat_model":{{"name":"{}","type":"chat"}},"extras":{{}}}}"#,
+            model
+        ))
+        .unwrap();
+        api_server.chat_model.as_mut().unwrap().prompt_template = prompt_template;
+
+        state.models.write().await.insert(
+            server.id.clone(),
+            vec![endpoints::models::Model {
+                id: model.to_string(),
+                created: 0,
+                object: "model".to_string(),
+                owned_by: "Not specified".to_string(),
+            }],
+        );
+        state
+            .server_info
+            .write()
+            .await
+            .servers
+            .insert(server.id.clone(), api_server);
+        state.register_downstream_server(server).await.unwrap();
+    }
+
+    let mut chat_request = ChatCompletionRequest {
+        model: Some("gpt-4o-mini".to_string()),
+        ..Default::default()
+    };
+    let route = crate::handler::chat_route(&state, &HeaderMap::new(), &mut chat_request, "test")
+        .await
+        .unwrap();
+    assert_eq!(route.model.as_deref(), Some("Llama-3.2-3B"));
+    assert_eq!(
+        chat_prompt_template(&state, &route, "test").await.unwrap(),
+        PromptTemplateType::Llama3Chat
+    );
+}
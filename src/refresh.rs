@@ -0,0 +1,116 @@
+use crate::{
+    dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+    handler::admin::fetch_server_metadata,
+    info,
+    server::{Server, ServerId},
+    AppState,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+/// Changes found by refreshing the server info and the model list of a server
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct RefreshReport {
+    pub(crate) id: ServerId,
+    /// Changed fields of the server info, as `path: old -> new`
+    pub(crate) info_changes: Vec<String>,
+    pub(crate) models_added: Vec<String>,
+    pub(crate) models_removed: Vec<String>,
+}
+impl RefreshReport {
+    /// Returns the total number of changes
+    pub(crate) fn num_changes(&self) -> usize {
+        self.info_changes.len() + self.models_added.len() + self.models_removed.len()
+    }
+}
+
+/// Re-pulls the server info and the model list of a server and stores them
+pub(crate) async fn refresh_server(
+    state: &AppState,
+    server: &Server,
+    request_id: impl AsRef<str>,
+) -> ServerResult<RefreshReport> {
+    let (api_server, server_models) = fetch_server_metadata(request_id, server).await?;
+
+    let mut report = RefreshReport {
+        id: server.id.clone(),
+        ..Default::default()
+    };
+
+    {
+        // hold the registry until the metadata is stored, so that the server cannot be
+        // unregistered or moved to another url in between
+        let servers = state.servers.read().await;
+        let mut server_info = state.server_info.write().await;
+        let mut models = state.models.write().await;
+
+        // the server may have been unregistered or updated in the meantime
+        match servers.get(&server.id) {
+            Some(current) if current.url == server.url => {}
+            Some(current) => {
+                return Err(ServerError::Operation(format!(
+                    "Server {} moved from {} to {} while it was refreshed",
+                    server.id, server.url, current.url
+                )))
+            }
+            None => return Err(ServerError::NotFoundServerId(server.id.clone())),
+        }
+
+        // update the server info
+        if let Some(old) = server_info.servers.get(&server.id) {
+            report.info_changes = info::diff(old, &api_server);
+        }
+        server_info.servers.insert(server.id.clone(), api_server);
+
+        // update the models
+        let new_ids = server_models
+            .iter()
+            .map(|model| model.id.clone())
+            .collect::<BTreeSet<_>>();
+        let old_ids = models
+            .get(&server.id)
+            .map(|models| models.iter().map(|model| model.id.clone()).collect())
+            .unwrap_or_else(BTreeSet::new);
+
+        report.models_added = new_ids.difference(&old_ids).cloned().collect();
+        report.models_removed = old_ids.difference(&new_ids).cloned().collect();
+        models.insert(server.id.clone(), server_models);
+    }
+
+    for change in report.info_changes.iter() {
+        dual_info!("Server info of {} changed: {}", server.id, change);
+    }
+    for model in report.models_added.iter() {
+        dual_info!("Server {} now serves the model {}", server.id, model);
+    }
+    for model in report.models_removed.iter() {
+        dual_info!("Server {} no longer serves the model {}", server.id, model);
+    }
+
+    Ok(report)
+}
+
+/// Periodically refreshes the server info and the model list of every registered server
+pub(crate) async fn run_refresher(state: Arc<AppState>) {
+    dual_info!("Server info refresher started");
+
+    loop {
+        let interval = state.config.read().await.refresh.interval;
+        tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+
+        let servers = state.downstream_servers().await;
+        let results = futures_util::future::join_all(
+            servers
+                .iter()
+                .map(|server| refresh_server(&state, server, "refresh")),
+        )
+        .await;
+
+        for (server, result) in servers.iter().zip(results) {
+            if let Err(e) = result {
+                dual_warn!("Failed to refresh server {}: {}", server.id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use crate::{
+    dual_error, dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+    handler::admin::verify_and_register,
+    info::ApiServer,
+    server::{default_weight, Secret, Server, ServerFlavor, ServerId, ServerKind, ServerMetadata},
+    AppState,
+};
+use endpoints::models::Model;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Persisted form of a registered server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ServerRecord {
+    pub(crate) id: ServerId,
+    pub(crate) url: String,
+    pub(crate) kind: ServerKind,
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32,
+    #[serde(default)]
+    pub(crate) priority: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_concurrency: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ttl_seconds: Option<u64>,
+    #[serde(default)]
+    pub(crate) flavor: ServerFlavor,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<ServerMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) labels: BTreeMap<String, String>,
+}
+impl From<&Server> for ServerRecord {
+    fn from(server: &Server) -> Self {
+        Self {
+            id: server.id.clone(),
+            url: server.url.clone(),
+            kind: server.kind,
+            weight: server.weight,
+            priority: server.priority,
+            max_concurrency: server.max_concurrency,
+            ttl_seconds: server.ttl_seconds,
+            flavor: server.flavor,
+            metadata: server.metadata.clone(),
+            api_key: server
+                .api_key
+                .as_ref()
+                .map(|api_key| api_key.expose().to_string()),
+            headers: server
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.expose().to_string()))
+                .collect(),
+            labels: server.labels.clone(),
+        }
+    }
+}
+impl From<ServerRecord> for Server {
+    fn from(record: ServerRecord) -> Self {
+        let mut server = Server::new(record.id, record.url, record.kind);
+        server.weight = record.weight;
+        server.priority = record.priority;
+        server.max_concurrency = record.max_concurrency;
+        server.ttl_seconds = record.ttl_seconds;
+        server.flavor = record.flavor;
+        server.metadata = record.metadata;
+        server.api_key = record.api_key.map(Secret::from);
+        server.headers = record
+            .headers
+            .into_iter()
+            .map(|(name, value)| (name, Secret::from(value)))
+            .collect();
+        server.labels = record.labels;
+        server
+    }
+}
+
+/// Content of the state file
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct RegistryFile {
+    pub(crate) servers: Vec<ServerRecord>,
+}
+
+/// Exported form of a registered server, with the server info and the models it reported
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ExportedServer {
+    #[serde(flatten)]
+    pub(crate) record: ServerRecord,
+    /// Names of the secrets of the server left out of the export, e.g. `api_key` or
+    /// `headers.x-api-version`, which have to be filled in before the server is imported
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) secrets: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) info: Option<ApiServer>,
+    pub(crate) models: Vec<Model>,
+}
+impl From<&Server> for ExportedServer {
+    fn from(server: &Server) -> Self {
+        let mut record = ServerRecord::from(server);
+
+        // the admin API is not authenticated, so secrets are only referenced by name
+        let mut secrets = Vec::new();
+        if record.api_key.take().is_some() {
+            secrets.push("api_key".to_string());
+        }
+        let mut headers = std::mem::take(&mut record.headers)
+            .into_keys()
+            .map(|name| format!("headers.{}", name))
+            .collect::<Vec<_>>();
+        headers.sort();
+        secrets.extend(headers);
+
+        Self {
+            record,
+            secrets,
+            info: None,
+            models: Vec::new(),
+        }
+    }
+}
+
+/// Request body of the admin endpoint that imports servers. An export can be imported as is.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ImportRequest {
+    /// Servers in the registration format. Each one is parsed on its own so that an invalid
+    /// entry does not fail the others.
+    pub(crate) servers: Vec<serde_json::Value>,
+}
+
+/// Outcome of importing a single server
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ImportResult {
+    pub(crate) url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<ServerId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) kind: Option<ServerKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+/// Returns the registered servers with their server info and models, sorted by id. The secrets
+/// of the servers are left out.
+pub(crate) async fn export(state: &AppState) -> Vec<ExportedServer> {
+    let servers = state.downstream_servers().await;
+    let server_info = state.server_info.read().await;
+    let models = state.models.read().await;
+
+    servers
+        .iter()
+        .map(|server| ExportedServer {
+            info: server_info.servers.get(&server.id).cloned(),
+            models: models.get(&server.id).cloned().unwrap_or_default(),
+            ..ExportedServer::from(server)
+        })
+        .collect()
+}
+
+/// Verifies and registers the given servers in parallel. Every server gets a new id. An entry
+/// repeating the url of an earlier entry fails without being verified.
+pub(crate) async fn import(
+    state: Arc<AppState>,
+    request_id: &str,
+    entries: Vec<serde_json::Value>,
+    replace: bool,
+) -> Vec<ImportResult> {
+    let mut seen_urls = HashSet::new();
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let url = entry
+                .get("url")
+                .and_then(|url| url.as_str())
+                .map(|url| url.to_string());
+            let duplicate = url
+                .as_ref()
+                .is_some_and(|url| !seen_urls.insert(url.trim_end_matches('/').to_string()));
+            (entry, url, duplicate)
+        })
+        .collect::<Vec<_>>();
+
+    futures_util::future::join_all(entries.into_iter().map(|(entry, url, duplicate)| {
+        let state = state.clone();
+        async move {
+            let result = match serde_json::from_value::<Server>(entry) {
+                Ok(server) if duplicate => {
+                    let err_msg = format!(
+                        "The url {} appears more than once in the import",
+                        server.url
+                    );
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    Err(ServerError::DuplicateServer(err_msg))
+                }
+                Ok(server) => verify_and_register(state, request_id, server, replace).await,
+                Err(e) => {
+                    let err_msg = format!("Invalid server: {}", e);
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    Err(ServerError::Operation(err_msg))
+                }
+            };
+
+            match result {
+                Ok(server) => ImportResult {
+                    url,
+                    id: Some(server.id),
+                    kind: Some(server.kind),
+                    error: None,
+                },
+                Err(e) => ImportResult {
+                    url,
+                    id: None,
+                    kind: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        }
+    }))
+    .await
+}
+
+/// Writes the registered servers to the state file, if one is configured
+pub(crate) async fn save(state: &AppState) -> ServerResult<()> {
+    let path = match state.config.read().await.server.state_file.clone() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    // serialize the writers so that the latest registry always ends up in the file
+    let _lock = state.registry_lock.lock().await;
+
+    let mut servers = state
+        .downstream_servers()
+        .await
+        .iter()
+        .map(ServerRecord::from)
+        .collect::<Vec<_>>();
+
+    // keep the restored servers whose registration is still retried
+    let registered = servers
+        .iter()
+        .map(|record| record.id.clone())
+        .collect::<HashSet<_>>();
+    servers.extend(
+        state
+            .pending_restores
+            .read()
+            .await
+            .values()
+            .filter(|record| !registered.contains(&record.id))
+            .cloned(),
+    );
+    servers.sort_by(|a, b| a.id.cmp(&b.id));
+
+    write_atomically(&path, &RegistryFile { servers }).map_err(|e| {
+        let err_msg = format!(
+            "Failed to persist the registry to {}: {}",
+            path.display(),
+            e
+        );
+        dual_error!("{}", err_msg);
+        ServerError::Operation(err_msg)
+    })
+}
+
+/// Loads the state file, if one is configured, and registers the servers that pass verification.
+/// The servers that fail verification stay in the state file and are retried in the background.
+pub(crate) async fn restore(state: Arc<AppState>) {
+    let (path, static_urls) = {
+        let config = state.config.read().await;
+        let static_urls = config
+            .servers
+            .iter()
+            .map(|server| server.url.clone())
+            .collect::<HashSet<_>>();
+        match config.server.state_file.clone() {
+            Some(path) => (path, static_urls),
+            None => return,
+        }
+    };
+
+    if !path.exists() {
+        dual_info!("No state file found at {}", path.display());
+        return;
+    }
+
+    let registry = match std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str::<RegistryFile>(&s).map_err(|e| e.to_string()))
+    {
+        Ok(registry) => registry,
+        Err(e) => {
+            dual_error!("Failed to load the state file {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    // the servers declared in the config file are registered from there
+    let records = registry
+        .servers
+        .into_iter()
+        .filter(|record| !static_urls.contains(&record.url))
+        .collect::<Vec<_>>();
+
+    dual_info!(
+        "Restoring {} server(s) from {}",
+        records.len(),
+        path.display()
+    );
+
+    let results = futures_util::future::join_all(records.into_iter().map(|record| {
+        let state = state.clone();
+        async move {
+            let result =
+                verify_and_register(state, "restore", Server::from(record.clone()), false).await;
+            (record, result.map(|_| ()))
+        }
+    }))
+    .await;
+
+    for (record, result) in results {
+        match result {
+            Ok(()) => dual_info!("Restored server {}", record.id),
+            // the url is taken by another server
+            Err(e @ ServerError::DuplicateServer(_)) => {
+                dual_warn!("Failed to restore server {}: {}", record.id, e)
+            }
+            Err(e) => {
+                dual_warn!(
+                    "Failed to restore server {}: {}. Retrying in the background.",
+                    record.id,
+                    e
+                );
+
+                state
+                    .pending_restores
+                    .write()
+                    .await
+                    .insert(record.id.clone(), record.clone());
+
+                tokio::spawn(retry_registration(
+                    state.clone(),
+                    Server::from(record),
+                    true,
+                ));
+            }
+        }
+    }
+}
+
+/// Registers the servers declared in the config file. The servers that cannot be registered
+/// yet are retried in the background until they come up.
+pub(crate) async fn register_static_servers(state: Arc<AppState>) {
+    let servers = state.config.read().await.servers.clone();
+    if servers.is_empty() {
+        return;
+    }
+
+    dual_info!(
+        "Registering {} server(s) from the config file",
+        servers.len()
+    );
+
+    let results = futures_util::future::join_all(servers.into_iter().map(|server| {
+        let state = state.clone();
+        async move {
+            let result = verify_and_register(state, "startup", server.clone(), false).await;
+            (server, result)
+        }
+    }))
+    .await;
+
+    for (server, result) in results {
+        match result {
+            Ok(registered) => dual_info!("Registered server {} at {}", registered.id, server.url),
+            Err(e) => {
+                dual_warn!(
+                    "Failed to register server {} at {}: {}. Retrying in the background.",
+                    server.id,
+                    server.url,
+                    e
+                );
+                tokio::spawn(retry_registration(state.clone(), server, false));
+            }
+        }
+    }
+}
+
+// Retry the registration of a server with backoff until it succeeds. A restored server is only
+// retried while it is pending, i.e. until it is unregistered.
+async fn retry_registration(state: Arc<AppState>, server: Server, restored: bool) {
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        tokio::time::sleep(backoff).await;
+
+        if restored && !state.pending_restores.read().await.contains_key(&server.id) {
+            dual_info!(
+                "Stopped retrying the registration of server {}, which was unregistered",
+                server.id
+            );
+            return;
+        }
+
+        match verify_and_register(state.clone(), "startup", server.clone(), false).await {
+            Ok(registered) => {
+                // the server may have been unregistered while it was verified
+                if restored
+                    && state
+                        .pending_restores
+                        .write()
+                        .await
+                        .remove(&server.id)
+                        .is_none()
+                {
+                    dual_info!(
+                        "Server {} was unregistered while it was verified, unregister it again",
+                        server.id
+                    );
+                    let _ = state.unregister_downstream_server(&server.id).await;
+
+                    // persist the registry. A failure is logged, but does not undo the change.
+                    let _ = save(&state).await;
+                    return;
+                }
+
+                dual_info!("Registered server {} at {}", registered.id, server.url);
+                return;
+            }
+            // retrying does not help if the url is taken by another server
+            Err(e @ ServerError::DuplicateServer(_)) => {
+                dual_warn!("Gave up registering server {}: {}", server.id, e);
+                if restored {
+                    state.pending_restores.write().await.remove(&server.id);
+
+                    // persist the registry. A failure is logged, but does not undo the change.
+                    let _ = save(&state).await;
+                }
+                return;
+            }
+            Err(e) => {
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                dual_warn!(
+                    "Failed to register server {} at {}: {}. Retrying in {} seconds.",
+                    server.id,
+                    server.url,
+                    e,
+                    backoff.as_secs()
+                );
+            }
+        }
+    }
+}
+
+// Write the registry to a temporary file next to the target and move it into place. The file
+// holds the credentials of the servers, so on unix it is only readable by its owner.
+fn write_atomically(path: &Path, registry: &RegistryFile) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    {
+        // the mode only applies to a new file, so drop a leftover of a failed write
+        let _ = std::fs::remove_file(&tmp_path);
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&tmp_path)?;
+        serde_json::to_writer_pretty(&mut file, registry)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+    }
+
+    std::fs::rename(&tmp_path, path)
+}
+
+#[test]
+fn test_server_record_roundtrip() {
+    let record: ServerRecord = serde_json::from_str(
+        r#"{"id": "chat-server-1", "url": "http://localhost:8000", "kind": "chat"}"#,
+    )
+    .unwrap();
+    assert_eq!(record.weight, 1);
+
+    let server = Server::from(record.clone());
+    assert_eq!(server.id, "chat-server-1");
+    assert_eq!(server.kind, ServerKind::chat);
+    assert_eq!(ServerRecord::from(&server), record);
+
+    // the state file keeps the credentials the listing redacts
+    let record: ServerRecord = serde_json::from_str(
+        r#"{"id": "chat-server-2", "url": "http://localhost:8001", "kind": "chat", "api_key": "sk-123", "headers": {"x-org": "nexus"}}"#,
+    )
+    .unwrap();
+    let server = Server::from(record.clone());
+    assert!(!serde_json::to_string(&server).unwrap().contains("sk-123"));
+    assert_eq!(ServerRecord::from(&server), record);
+}
+
+#[test]
+fn test_import_exported_server() {
+    let mut server = Server::new(
+        "chat-server-1".to_string(),
+        "http://localhost:8000",
+        ServerKind::chat,
+    );
+    server.weight = 3;
+    server.api_key = Some(Secret::from("sk-123".to_string()));
+    server.headers.insert(
+        "x-api-version".to_string(),
+        Secret::from("2024-10-01".to_string()),
+    );
+
+    // the secrets are referenced by name only
+    let entry = serde_json::to_value(ExportedServer::from(&server)).unwrap();
+    assert!(!entry.to_string().contains("sk-123"));
+    assert!(!entry.to_string().contains("2024-10-01"));
+    assert_eq!(
+        entry["secrets"],
+        serde_json::json!(["api_key", "headers.x-api-version"])
+    );
+
+    // an export can be imported as is
+    let imported: Server = serde_json::from_value(entry).unwrap();
+    assert_ne!(imported.id, server.id);
+    assert_eq!(imported.url, server.url);
+    assert_eq!(imported.kind, server.kind);
+    assert_eq!(imported.weight, 3);
+    assert_eq!(imported.api_key, None);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_state_file_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("nexus-state-{}.json", uuid::Uuid::new_v4()));
+    write_atomically(&path, &RegistryFile::default()).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[tokio::test]
+async fn test_unregister_pending_restore() {
+    let state = AppState::new(Default::default(), Default::default());
+    let record: ServerRecord = serde_json::from_str(
+        r#"{"id": "chat-server-1", "url": "http://localhost:8000", "kind": "chat"}"#,
+    )
+    .unwrap();
+    state
+        .pending_restores
+        .write()
+        .await
+        .insert(record.id.clone(), record);
+
+    // a pending server is listed until it is unregistered
+    let servers = state.list_downstream_servers().await.unwrap();
+    assert!(servers[&ServerKind::chat][0].pending);
+
+    state
+        .unregister_downstream_server("chat-server-1")
+        .await
+        .unwrap();
+    assert!(state.pending_restores.read().await.is_empty());
+    assert!(state.list_downstream_servers().await.unwrap().is_empty());
+}
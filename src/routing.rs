@@ -0,0 +1,288 @@
+use crate::{error::ServerError, server::ServerKind};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Strategy used by a server group to pick a downstream server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RoutingStrategy {
+    /// Cycle through the servers in order
+    RoundRobin,
+    /// Pick a random server with a probability proportional to its weight
+    WeightedRandom,
+    /// Pick the server with the fewest in-flight requests
+    #[default]
+    LeastConnections,
+    /// Pick the server with the lowest EWMA latency, scaled by its in-flight requests
+    LeastLatency,
+}
+impl std::fmt::Display for RoutingStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoutingStrategy::RoundRobin => write!(f, "round-robin"),
+            RoutingStrategy::WeightedRandom => write!(f, "weighted-random"),
+            RoutingStrategy::LeastConnections => write!(f, "least-connections"),
+            RoutingStrategy::LeastLatency => write!(f, "least-latency"),
+        }
+    }
+}
+impl std::str::FromStr for RoutingStrategy {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "round-robin" => Ok(RoutingStrategy::RoundRobin),
+            "weighted-random" => Ok(RoutingStrategy::WeightedRandom),
+            "least-connections" => Ok(RoutingStrategy::LeastConnections),
+            "least-latency" => Ok(RoutingStrategy::LeastLatency),
+            _ => Err(ServerError::BadRequest(format!(
+                "Invalid routing strategy: {}. Possible values: round-robin, weighted-random, least-connections, least-latency",
+                s
+            ))),
+        }
+    }
+}
+impl RoutingStrategy {
+    /// Picks one of the candidates and returns its index. Only the candidates of the highest
+    /// priority tier are considered, within which traffic is spread in proportion to the weights.
+    /// `cursor` is the round-robin position of the group.
+    pub(crate) fn select(&self, candidates: &[Candidate], cursor: &AtomicUsize) -> Option<usize> {
+        // lower tiers only take traffic if no server of a higher tier is eligible
+        let top_priority = candidates.iter().map(|c| c.priority).max()?;
+        let tier = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.priority == top_priority)
+            .collect::<Vec<_>>();
+
+        // a weight of 0 is rejected at registration, the floor of 1 only keeps the arithmetic safe
+        let weight = |c: &Candidate| c.weight.max(1) as u64;
+
+        let picked = match self {
+            RoutingStrategy::RoundRobin => {
+                // smooth the cycle by giving each server as many turns as its weight
+                let total: u64 = tier.iter().map(|(_, c)| weight(c)).sum();
+                let mut point = cursor.fetch_add(1, Ordering::Relaxed) as u64 % total;
+                let mut picked = tier.len() - 1;
+                for (pos, (_, candidate)) in tier.iter().enumerate() {
+                    let turns = weight(candidate);
+                    if point < turns {
+                        picked = pos;
+                        break;
+                    }
+                    point -= turns;
+                }
+                picked
+            }
+            RoutingStrategy::WeightedRandom => {
+                let total: u64 = tier.iter().map(|(_, c)| weight(c)).sum();
+                let mut point = rand::thread_rng().gen_range(0..total);
+                let mut picked = tier.len() - 1;
+                for (pos, (_, candidate)) in tier.iter().enumerate() {
+                    if point < weight(candidate) {
+                        picked = pos;
+                        break;
+                    }
+                    point -= weight(candidate);
+                }
+                picked
+            }
+            RoutingStrategy::LeastConnections => {
+                let score = |c: &Candidate| c.in_flight as f64 / weight(c) as f64;
+
+                tier.iter()
+                    .enumerate()
+                    .min_by(|(_, (_, a)), (_, (_, b))| score(a).total_cmp(&score(b)))
+                    .map(|(pos, _)| pos)?
+            }
+            RoutingStrategy::LeastLatency => {
+                // servers without any latency sample yet are tried first
+                let score = |c: &Candidate| match c.latency {
+                    Some(latency) => latency * (c.in_flight + 1) as f64 / weight(c) as f64,
+                    None => 0.0,
+                };
+
+                tier.iter()
+                    .enumerate()
+                    .min_by(|(_, (_, a)), (_, (_, b))| score(a).total_cmp(&score(b)))
+                    .map(|(pos, _)| pos)?
+            }
+        };
+
+        Some(tier[picked].0)
+    }
+}
+
+/// Request body of the admin endpoint that switches routing strategies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RoutingStrategyUpdate {
+    pub kind: ServerKind,
+    pub strategy: RoutingStrategy,
+}
+
+/// Routing-relevant state of a server eligible for a request
+#[derive(Debug, Clone)]
+pub(crate) struct Candidate {
+    pub(crate) in_flight: usize,
+    pub(crate) weight: u32,
+    /// Servers of a lower priority only take traffic if no server of a higher priority can
+    pub(crate) priority: i32,
+    /// EWMA latency in milliseconds, if any request has been observed
+    pub(crate) latency: Option<f64>,
+}
+
+/// Exponentially weighted moving average of the response latency of a server
+#[derive(Debug, Default)]
+pub(crate) struct LatencyEwma {
+    // f64 milliseconds stored as bits; 0 means no sample yet
+    bits: AtomicU64,
+}
+impl LatencyEwma {
+    /// Weight of the newest sample
+    const ALPHA: f64 = 0.3;
+
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let sample = elapsed.as_secs_f64() * 1000.0;
+        let _ = self
+            .bits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let updated = match bits {
+                    0 => sample,
+                    _ => Self::ALPHA * sample + (1.0 - Self::ALPHA) * f64::from_bits(bits),
+                };
+                Some(updated.to_bits())
+            });
+    }
+
+    /// Returns the current average in milliseconds
+    pub(crate) fn get(&self) -> Option<f64> {
+        match self.bits.load(Ordering::Relaxed) {
+            0 => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+}
+
+#[test]
+fn test_parse_routing_strategy() {
+    let strategy: RoutingStrategy = serde_json::from_str("\"least-latency\"").unwrap();
+    assert_eq!(strategy, RoutingStrategy::LeastLatency);
+    assert_eq!(strategy.to_string(), "least-latency");
+
+    let strategy: RoutingStrategy = "Round-Robin".parse().unwrap();
+    assert_eq!(strategy, RoutingStrategy::RoundRobin);
+
+    assert!("fastest".parse::<RoutingStrategy>().is_err());
+}
+
+#[test]
+fn test_select_candidate() {
+    let candidates = vec![
+        Candidate {
+            in_flight: 3,
+            weight: 1,
+            priority: 0,
+            latency: Some(20.0),
+        },
+        Candidate {
+            in_flight: 1,
+            weight: 2,
+            priority: 0,
+            latency: Some(100.0),
+        },
+        Candidate {
+            in_flight: 2,
+            weight: 1,
+            priority: 0,
+            latency: Some(10.0),
+        },
+    ];
+    let cursor = AtomicUsize::new(0);
+
+    let picked = (0..4)
+        .map(|_| RoutingStrategy::RoundRobin.select(&candidates, &cursor))
+        .collect::<Vec<_>>();
+    assert_eq!(picked, vec![Some(0), Some(1), Some(1), Some(2)]);
+
+    assert_eq!(
+        RoutingStrategy::LeastConnections.select(&candidates, &cursor),
+        Some(1)
+    );
+    assert_eq!(
+        RoutingStrategy::LeastLatency.select(&candidates, &cursor),
+        Some(2)
+    );
+    assert_eq!(RoutingStrategy::LeastConnections.select(&[], &cursor), None);
+}
+
+#[test]
+fn test_latency_ewma() {
+    let ewma = LatencyEwma::default();
+    assert_eq!(ewma.get(), None);
+
+    ewma.record(Duration::from_millis(100));
+    assert_eq!(ewma.get(), Some(100.0));
+
+    ewma.record(Duration::from_millis(200));
+    assert!((ewma.get().unwrap() - 130.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_select_weighted_tiers() {
+    let candidate = |weight, priority| Candidate {
+        in_flight: 0,
+        weight,
+        priority,
+        latency: None,
+    };
+    let cursor = AtomicUsize::new(0);
+
+    // round-robin gives the servers turns in proportion to their weights
+    let candidates = vec![candidate(3, 0), candidate(1, 0)];
+    let picked = (0..8)
+        .filter_map(|_| RoutingStrategy::RoundRobin.select(&candidates, &cursor))
+        .filter(|idx| *idx == 0)
+        .count();
+    assert_eq!(picked, 6);
+
+    // weighted-random picks the servers with a probability proportional to their weights
+    let picked = (0..1000)
+        .filter_map(|_| RoutingStrategy::WeightedRandom.select(&candidates, &cursor))
+        .filter(|idx| *idx == 0)
+        .count();
+    assert!((650..850).contains(&picked), "{}", picked);
+
+    // the fallback tier only takes traffic once the preferred tier is gone
+    let candidates = vec![candidate(1, -1), candidate(1, 1), candidate(1, 1)];
+    for strategy in [
+        RoutingStrategy::RoundRobin,
+        RoutingStrategy::WeightedRandom,
+        RoutingStrategy::LeastConnections,
+        RoutingStrategy::LeastLatency,
+    ] {
+        for _ in 0..10 {
+            assert_ne!(strategy.select(&candidates, &cursor), Some(0));
+        }
+        assert_eq!(strategy.select(&candidates[..1], &cursor), Some(0));
+    }
+
+    // least-connections scales the in-flight requests by the weight
+    let candidates = vec![
+        Candidate {
+            in_flight: 2,
+            ..candidate(1, 0)
+        },
+        Candidate {
+            in_flight: 3,
+            ..candidate(2, 0)
+        },
+    ];
+    assert_eq!(
+        RoutingStrategy::LeastConnections.select(&candidates, &cursor),
+        Some(1)
+    );
+}
@@ -1,11 +1,27 @@
-use crate::error::{ServerError, ServerResult};
+use crate::{
+    circuit_breaker::{BreakerState, BreakerStatus, CircuitBreaker, Dispatch, Trial},
+    config::CircuitBreakerConfig,
+    dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+    health::HealthStatus,
+    queue::WaitQueue,
+    routing::{Candidate, LatencyEwma, RoutingStrategy},
+};
 use async_trait::async_trait;
-use axum::http::Uri;
+use axum::http::{
+    header::{HeaderName, AUTHORIZATION},
+    HeaderMap, HeaderValue, Uri,
+};
 use bitflags::bitflags;
+use chat_prompts::PromptTemplateType;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
-    sync::atomic::{AtomicUsize, Ordering},
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 use tokio::sync::RwLock;
 
@@ -22,8 +38,153 @@ pub struct Server {
     pub id: ServerId,
     pub url: String,
     pub kind: ServerKind,
+    /// Relative share of traffic for weighted routing strategies
+    #[serde(skip_serializing_if = "is_default_weight")]
+    pub weight: u32,
+    /// Servers of a lower priority only receive traffic if every server of a higher priority is
+    /// unavailable or saturated
+    #[serde(skip_serializing_if = "is_default_priority")]
+    pub priority: i32,
+    /// Maximum number of in-flight requests. A server at its limit is saturated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+    /// Lease of the registration. The server is unregistered if no heartbeat renews it in time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    /// API flavor of the server, which decides how it is verified
+    #[serde(skip_serializing_if = "ServerFlavor::is_default")]
+    pub flavor: ServerFlavor,
+    /// User-supplied metadata of a server that has no `/v1/info` endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ServerMetadata>,
+    /// Key sent as a bearer token with every request to the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<Secret>,
+    /// Extra headers sent with every request to the server
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, Secret>,
+    /// Labels such as `gpu=a100`, matched against the label selector of a request
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Number of in-flight requests, shared by all clones of the server
+    #[serde(skip)]
+    connections: Arc<AtomicUsize>,
+    /// Response latency observed from real traffic, shared by all clones of the server
+    #[serde(skip)]
+    latency: Arc<LatencyEwma>,
+    /// Circuit breaker fed by real traffic, shared by all clones of the server
     #[serde(skip)]
-    connections: AtomicUsize,
+    breaker: Arc<CircuitBreaker>,
+    /// Whether the server is being drained, shared by all clones of the server
+    #[serde(skip)]
+    draining: Arc<AtomicBool>,
+}
+impl Server {
+    pub(crate) fn new(id: ServerId, url: impl Into<String>, kind: ServerKind) -> Self {
+        Self {
+            id,
+            url: url.into(),
+            kind,
+            weight: DEFAULT_WEIGHT,
+            priority: 0,
+            max_concurrency: None,
+            ttl_seconds: None,
+            flavor: ServerFlavor::default(),
+            metadata: None,
+            api_key: None,
+            headers: HashMap::new(),
+            labels: BTreeMap::new(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(LatencyEwma::default()),
+            breaker: Arc::new(CircuitBreaker::default()),
+            draining: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Generates a new server id. The id of a server whose kind is not known yet is regenerated
+    /// once the kind is inferred.
+    pub(crate) fn generate_id(kind: ServerKind) -> ServerId {
+        match kind.is_empty() {
+            true => format!("server-{}", uuid::Uuid::new_v4()),
+            false => {
+                let kind = kind.to_string().trim().replace(',', "-");
+                format!("{}-server-{}", kind, uuid::Uuid::new_v4())
+            }
+        }
+    }
+
+    /// Returns the number of requests currently being served by the server
+    pub(crate) fn in_flight(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the server has as many in-flight requests as it may serve
+    pub(crate) fn is_saturated(&self) -> bool {
+        self.max_concurrency
+            .is_some_and(|max_concurrency| self.in_flight() >= max_concurrency)
+    }
+
+    /// Returns the EWMA response latency of the server in milliseconds
+    pub(crate) fn latency(&self) -> Option<f64> {
+        self.latency.get()
+    }
+
+    /// Returns the state of the circuit breaker of the server
+    pub(crate) fn breaker(&self) -> BreakerStatus {
+        self.breaker.status()
+    }
+
+    /// Returns whether the server is being drained. A draining server receives no new requests.
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Relaxed);
+    }
+
+    /// Returns the credentials and the extra headers sent with every request to the server
+    pub(crate) fn auth_headers(&self) -> HeaderMap {
+        let mut auth_headers = HeaderMap::new();
+        for (name, value) in self.headers.iter() {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value.expose()),
+            ) {
+                auth_headers.insert(name, value);
+            }
+        }
+        if let Some(api_key) = &self.api_key {
+            if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", api_key.expose())) {
+                auth_headers.insert(AUTHORIZATION, value);
+            }
+        }
+
+        auth_headers
+    }
+
+    /// Adds the credentials and the extra headers of the server to a request to it, replacing
+    /// the headers of the same name already set on the request
+    pub(crate) fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request.headers(self.auth_headers())
+    }
+
+    /// Reserves an in-flight slot on the server unless it is saturated. The slot is released
+    /// when the guard is dropped.
+    pub(crate) fn try_acquire(&self) -> Option<ConnectionGuard> {
+        let max_concurrency = self.max_concurrency.unwrap_or(usize::MAX);
+        self.connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_flight| {
+                (in_flight < max_concurrency).then_some(in_flight + 1)
+            })
+            .ok()?;
+
+        Some(ConnectionGuard {
+            connections: self.connections.clone(),
+            queue: None,
+            trial: None,
+        })
+    }
 }
 impl<'de> Deserialize<'de> for Server {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -34,22 +195,88 @@ impl<'de> Deserialize<'de> for Server {
         #[derive(Deserialize)]
         struct ServerHelper {
             url: String,
-            kind: ServerKind,
+            #[serde(default)]
+            kind: Option<ServerKind>,
+            #[serde(default = "default_weight")]
+            weight: u32,
+            #[serde(default)]
+            priority: i32,
+            #[serde(default)]
+            max_concurrency: Option<usize>,
+            #[serde(default)]
+            ttl_seconds: Option<u64>,
+            #[serde(default)]
+            flavor: ServerFlavor,
+            #[serde(default)]
+            metadata: Option<ServerMetadata>,
+            #[serde(default, alias = "bearer_token")]
+            api_key: Option<Secret>,
+            #[serde(default)]
+            headers: HashMap<String, Secret>,
+            #[serde(default)]
+            labels: BTreeMap<String, String>,
         }
 
         // Deserialize into the helper struct
         let helper = ServerHelper::deserialize(deserializer)?;
 
-        let kind = helper.kind.to_string().trim().replace(',', "-");
-        let id = format!("{}-server-{}", kind, uuid::Uuid::new_v4());
+        if helper.weight == 0 {
+            return Err(serde::de::Error::custom(
+                "The weight of a server must be greater than 0",
+            ));
+        }
+        if helper.max_concurrency == Some(0) {
+            return Err(serde::de::Error::custom(
+                "The max_concurrency of a server must be greater than 0",
+            ));
+        }
+        if helper.ttl_seconds == Some(0) {
+            return Err(serde::de::Error::custom(
+                "The ttl_seconds of a server must be greater than 0",
+            ));
+        }
+
+        if let Some(api_key) = &helper.api_key {
+            if HeaderValue::from_str(api_key.expose()).is_err() {
+                return Err(serde::de::Error::custom(
+                    "The api_key of a server must be a valid header value",
+                ));
+            }
+        }
+        for (name, value) in helper.headers.iter() {
+            if HeaderName::from_bytes(name.as_bytes()).is_err()
+                || HeaderValue::from_str(value.expose()).is_err()
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "Invalid header of a server: {}",
+                    name
+                )));
+            }
+        }
+
+        validate_labels(&helper.labels).map_err(serde::de::Error::custom)?;
+
+        // the kind of an OpenAI-compatible server cannot be inferred from its server info
+        let kind = helper.kind.unwrap_or_else(ServerKind::empty);
+        if kind.is_empty() && helper.flavor == ServerFlavor::OpenAI {
+            return Err(serde::de::Error::custom(
+                "The kind of an OpenAI-compatible server must be given",
+            ));
+        }
 
         // Create the actual Server instance
-        Ok(Server {
-            id,
-            url: helper.url,
-            kind: helper.kind,
-            connections: AtomicUsize::new(0),
-        })
+        let mut server = Server::new(Server::generate_id(kind), helper.url, kind);
+        server.weight = helper.weight;
+        server.priority = helper.priority;
+        server.max_concurrency = helper.max_concurrency;
+        server.ttl_seconds = helper.ttl_seconds;
+        server.flavor = helper.flavor;
+        server.metadata = helper.metadata;
+        server.api_key = helper.api_key;
+        server.headers = helper.headers;
+        server.labels = helper.labels;
+
+        Ok(server)
     }
 }
 impl Clone for Server {
@@ -58,7 +285,212 @@ impl Clone for Server {
             id: self.id.clone(),
             url: self.url.clone(),
             kind: self.kind,
-            connections: AtomicUsize::new(self.connections.load(Ordering::Relaxed)),
+            weight: self.weight,
+            priority: self.priority,
+            max_concurrency: self.max_concurrency,
+            ttl_seconds: self.ttl_seconds,
+            flavor: self.flavor,
+            metadata: self.metadata.clone(),
+            api_key: self.api_key.clone(),
+            headers: self.headers.clone(),
+            labels: self.labels.clone(),
+            connections: self.connections.clone(),
+            latency: self.latency.clone(),
+            breaker: self.breaker.clone(),
+            draining: self.draining.clone(),
+        }
+    }
+}
+
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// API flavor of a downstream server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerFlavor {
+    /// A LlamaEdge API server, verified through `/v1/info`
+    #[default]
+    #[serde(rename = "llamaedge")]
+    LlamaEdge,
+    /// A generic OpenAI-compatible server, e.g. vLLM or llama.cpp, verified through `/v1/models`
+    #[serde(rename = "openai")]
+    OpenAI,
+}
+impl ServerFlavor {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Metadata of an OpenAI-compatible server that cannot be read from the server itself
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerMetadata {
+    /// Context size of the models of the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctx_size: Option<u64>,
+    /// Prompt template of the chat model of the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<PromptTemplateType>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extras: HashMap<String, String>,
+}
+
+pub(crate) fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
+
+fn is_default_weight(weight: &u32) -> bool {
+    *weight == DEFAULT_WEIGHT
+}
+
+fn is_default_priority(priority: &i32) -> bool {
+    *priority == 0
+}
+
+/// A secret, such as an API key, that is redacted when serialized or logged
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+impl Secret {
+    /// Returns the secret value
+    pub(crate) fn expose(&self) -> &str {
+        &self.0
+    }
+}
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", REDACTED)
+    }
+}
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+const REDACTED: &str = "********";
+
+/// Holds an in-flight slot on a downstream server and releases it on drop
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+    /// Queue of the group the slot was taken in, woken when the slot is released
+    queue: Option<Arc<WaitQueue>>,
+    /// Trial request of the circuit breaker of the server, ended with the request
+    trial: Option<Trial>,
+}
+impl ConnectionGuard {
+    /// Wakes the requests waiting in the given queue when the slot is released
+    pub(crate) fn notify_on_release(mut self, queue: Arc<WaitQueue>) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Ends the trial request of the circuit breaker when the slot is released
+    pub(crate) fn with_trial(mut self, trial: Option<Trial>) -> Self {
+        self.trial = trial;
+        self
+    }
+
+    /// Returns the id of the trial request of the circuit breaker, if this is one
+    pub(crate) fn trial_id(&self) -> Option<u64> {
+        self.trial.as_ref().map(Trial::id)
+    }
+}
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+        if let Some(queue) = &self.queue {
+            queue.notify();
+        }
+    }
+}
+
+/// A downstream server selected by a routing policy
+#[derive(Debug)]
+pub(crate) struct TargetServer {
+    pub(crate) id: ServerId,
+    pub(crate) url: Uri,
+    /// Credentials and extra headers of the server
+    pub(crate) auth_headers: HeaderMap,
+    /// Keeps the request counted against the server until the response is done. Released early
+    /// for a failed request that is retried on another server.
+    pub(crate) guard: Option<ConnectionGuard>,
+    started: Instant,
+    latency: Arc<LatencyEwma>,
+    breaker: Arc<CircuitBreaker>,
+}
+impl TargetServer {
+    /// Releases the in-flight slot of the request on the server
+    pub(crate) fn release(mut self) -> Self {
+        self.guard = None;
+        self
+    }
+
+    /// Records the time elapsed since the server was picked as a latency sample of the server
+    pub(crate) fn record_latency(&self) {
+        self.latency.record(self.started.elapsed());
+    }
+
+    /// Feeds the outcome of the request to the circuit breaker of the server. Responses slower
+    /// than the configured threshold count as failures.
+    pub(crate) fn record_outcome(&self, failed: bool, config: &CircuitBreakerConfig) {
+        let slow = config
+            .slow_request_ms
+            .is_some_and(|slow_ms| self.started.elapsed().as_millis() > slow_ms as u128);
+
+        let trial = self.guard.as_ref().and_then(|guard| guard.trial_id());
+        if let Some(state) = self.breaker.record(failed || slow, trial, config) {
+            match state {
+                BreakerState::Open => dual_warn!(
+                    "Circuit breaker of server {} opened at an error rate of {:.0}%",
+                    self.id,
+                    self.breaker.status().error_rate * 100.0
+                ),
+                _ => dual_info!("Circuit breaker of server {} is {}", self.id, state),
+            }
+        }
+    }
+}
+
+/// Snapshot of a registered server and its runtime state
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ServerStatus {
+    #[serde(flatten)]
+    pub(crate) server: Server,
+    pub(crate) in_flight: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) draining: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) health: Option<HealthStatus>,
+    pub(crate) circuit_breaker: BreakerStatus,
+    /// Seconds until the lease of the server expires, if it was registered with a TTL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) lease_expires_in: Option<u64>,
+    /// Whether the server was restored from the state file and its registration is still retried
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) pending: bool,
+}
+impl From<&Server> for ServerStatus {
+    fn from(server: &Server) -> Self {
+        Self {
+            server: server.clone(),
+            in_flight: server.in_flight(),
+            draining: server.is_draining(),
+            latency_ms: server.latency(),
+            health: None,
+            circuit_breaker: server.breaker(),
+            lease_expires_in: None,
+            pending: false,
         }
     }
 }
@@ -76,17 +508,25 @@ fn test_deserialize_server() {
     println!("id: {}", server.id);
     assert_eq!(server.url, "http://localhost:8000");
     assert_eq!(server.kind, ServerKind::chat);
+
+    // the kind is inferred at registration if it is omitted
+    let serialized = r#"{"url": "http://localhost:8000"}"#;
+    let server: Server = serde_json::from_str(serialized).unwrap();
+    assert!(server.kind.is_empty());
+    assert!(server.id.starts_with("server-"));
+
+    let serialized = r#"{"url": "http://localhost:8000", "flavor": "openai"}"#;
+    assert!(serde_json::from_str::<Server>(serialized).is_err());
 }
 
 #[test]
 fn test_serialize_server() {
     let id = "chat-tts-29b6c973-d45a-4487-a3da-2e9b1f704fd9".to_string();
-    let server = Server {
+    let server = Server::new(
         id,
-        url: "http://localhost:8000".to_string(),
-        kind: ServerKind::chat | ServerKind::tts,
-        connections: AtomicUsize::new(0),
-    };
+        "http://localhost:8000",
+        ServerKind::chat | ServerKind::tts,
+    );
     let serialized = serde_json::to_string(&server).unwrap();
     assert_eq!(
         serialized,
@@ -94,12 +534,7 @@ fn test_serialize_server() {
     );
 
     let id = "chat-2424f42e-fcfb-458e-9a6a-ad419e24b5f5".to_string();
-    let server: Server = Server {
-        id,
-        url: "http://localhost:8000".to_string(),
-        kind: ServerKind::chat,
-        connections: AtomicUsize::new(0),
-    };
+    let server: Server = Server::new(id, "http://localhost:8000", ServerKind::chat);
     let serialized = serde_json::to_string(&server).unwrap();
     assert_eq!(
         serialized,
@@ -107,6 +542,55 @@ fn test_serialize_server() {
     );
 }
 
+#[test]
+fn test_server_auth_headers() {
+    let server: Server = serde_json::from_str(
+        r#"{"url": "http://localhost:8000", "kind": "chat", "api_key": "sk-123", "headers": {"x-org": "nexus"}}"#,
+    )
+    .unwrap();
+    let auth_headers = server.auth_headers();
+    assert_eq!(auth_headers[AUTHORIZATION], "Bearer sk-123");
+    assert_eq!(auth_headers["x-org"], "nexus");
+
+    // the secrets are kept out of the listing and the logs
+    assert!(!serde_json::to_string(&server).unwrap().contains("sk-123"));
+    assert!(!format!("{:?}", server).contains("sk-123"));
+
+    assert!(serde_json::from_str::<Server>(
+        r#"{"url": "http://localhost:8000", "kind": "chat", "headers": {"bad header": "x"}}"#
+    )
+    .is_err());
+}
+
+#[test]
+fn test_connection_guard() {
+    let server = Server::new(
+        "chat-server-1".to_string(),
+        "http://localhost:8000",
+        ServerKind::chat,
+    );
+    let cloned = server.clone();
+
+    let guard1 = server.try_acquire().unwrap();
+    let guard2 = cloned.try_acquire().unwrap();
+    assert_eq!(server.in_flight(), 2);
+    assert_eq!(cloned.in_flight(), 2);
+
+    drop(guard1);
+    assert_eq!(server.in_flight(), 1);
+    drop(guard2);
+    assert_eq!(server.in_flight(), 0);
+
+    // a saturated server hands out no slot until one is released
+    let mut server = server;
+    server.max_concurrency = Some(1);
+    let guard = server.try_acquire().unwrap();
+    assert!(server.is_saturated());
+    assert!(server.try_acquire().is_none());
+    drop(guard);
+    assert!(server.try_acquire().is_some());
+}
+
 bitflags! {
     /// Represents the kind of server
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -254,19 +738,27 @@ pub(crate) struct ServerGroup {
     pub(crate) servers: RwLock<Vec<RwLock<Server>>>,
     pub(crate) healthy_servers: RwLock<HashSet<ServerId>>,
     ty: ServerKind,
+    strategy: RwLock<RoutingStrategy>,
+    // round-robin position
+    cursor: AtomicUsize,
+    /// Requests waiting for a saturated server of the group
+    queue: Arc<WaitQueue>,
 }
 impl ServerGroup {
-    pub(crate) fn new(ty: ServerKind) -> Self {
+    pub(crate) fn new(ty: ServerKind, strategy: RoutingStrategy) -> Self {
         Self {
             servers: RwLock::new(Vec::new()),
             healthy_servers: RwLock::new(HashSet::new()),
             ty,
+            strategy: RwLock::new(strategy),
+            cursor: AtomicUsize::new(0),
+            queue: Arc::new(WaitQueue::default()),
         }
     }
 
     pub(crate) async fn register(&self, server: Server) -> ServerResult<()> {
         // check if the server is already registered
-        if self.healthy_servers.read().await.contains(&server.id) {
+        if self.server_ids().await.contains(&server.id) {
             let err_msg = format!("Server already registered: {}", server.url);
             error!(target: "stdout", "{}", &err_msg);
             return Err(ServerError::Operation(err_msg));
@@ -299,33 +791,94 @@ impl ServerGroup {
         };
 
         // Remove the server from server list if found
-        if let Some(idx) = idx_to_remove {
-            let mut servers = self.servers.write().await;
-            servers.swap_remove(idx);
+        match idx_to_remove {
+            Some(idx) => {
+                let mut servers = self.servers.write().await;
+                servers.swap_remove(idx);
+            }
+            None => {
+                let err_msg = format!("Server not found: {}", id_to_remove);
+                error!(target: "stdout", "{}", &err_msg);
+                return Err(ServerError::Operation(err_msg));
+            }
         }
 
-        // Remove the server from the healthy server set if found
-        if !self.healthy_servers.write().await.remove(id_to_remove) {
-            let err_msg = format!("Server not found: {}", id_to_remove);
-            error!(target: "stdout", "{}", &err_msg);
-            return Err(ServerError::Operation(err_msg));
-        }
+        // Remove the server from the healthy server set
+        self.healthy_servers.write().await.remove(id_to_remove);
 
         Ok(())
     }
 
+    /// Applies an update to the server with the given id. Returns whether the server was found.
+    pub(crate) async fn update_server(
+        &self,
+        server_id: impl AsRef<str>,
+        update: impl Fn(&mut Server),
+    ) -> bool {
+        let server_id = server_id.as_ref();
+
+        for server_lock in self.servers.read().await.iter() {
+            let mut server = server_lock.write().await;
+            if server.id == server_id {
+                update(&mut server);
+                return true;
+            }
+        }
+
+        false
+    }
+
     #[allow(dead_code)]
     pub(crate) async fn ty(&self) -> ServerKind {
         self.ty
     }
 
+    pub(crate) async fn strategy(&self) -> RoutingStrategy {
+        *self.strategy.read().await
+    }
+
+    pub(crate) async fn set_strategy(&self, strategy: RoutingStrategy) {
+        *self.strategy.write().await = strategy;
+    }
+
+    /// Returns the queue of the requests waiting for a saturated server of the group
+    pub(crate) fn queue(&self) -> Arc<WaitQueue> {
+        self.queue.clone()
+    }
+
     pub(crate) async fn is_empty(&self) -> bool {
-        self.healthy_servers.read().await.is_empty()
+        self.servers.read().await.is_empty()
+    }
+
+    /// Marks a server as healthy or unhealthy. Unhealthy servers are skipped by the routing policy.
+    pub(crate) async fn set_healthy(&self, server_id: impl AsRef<str>, healthy: bool) {
+        let server_id = server_id.as_ref();
+        if !self.server_ids().await.iter().any(|id| id == server_id) {
+            return;
+        }
+
+        let mut healthy_servers = self.healthy_servers.write().await;
+        match healthy {
+            true => healthy_servers.insert(server_id.to_string()),
+            false => healthy_servers.remove(server_id),
+        };
+    }
+
+    /// Returns the ids of all servers registered in the group
+    pub(crate) async fn server_ids(&self) -> Vec<ServerId> {
+        let servers = self.servers.read().await;
+
+        let mut ids = Vec::with_capacity(servers.len());
+        for server_lock in servers.iter() {
+            ids.push(server_lock.read().await.id.clone());
+        }
+
+        ids
     }
 }
 #[async_trait]
 impl RoutingPolicy for ServerGroup {
-    async fn next(&self) -> Result<Uri, ServerError> {
+    async fn next(&self, filter: &ServerFilter) -> Result<TargetServer, ServerError> {
         let servers = self.servers.read().await;
         if servers.is_empty() {
             let err_msg = format!("No {} server found", self.ty);
@@ -333,36 +886,353 @@ impl RoutingPolicy for ServerGroup {
             return Err(ServerError::NotFoundServer(self.ty.to_string()));
         }
 
-        let server_lock = if servers.len() == 1 {
-            servers.first().unwrap()
-        } else {
-            // Find server with minimum connections - need to read each server
-            let mut min_connections = usize::MAX;
-            let mut min_server = &servers[0];
+        // Collect the healthy servers allowed by the filter
+        let healthy_servers = self.healthy_servers.read().await;
+        let mut eligible = Vec::with_capacity(servers.len());
+        let mut candidates = Vec::with_capacity(servers.len());
+        let mut saturated = 0;
+        let mut preferred = None;
+        for server_lock in servers.iter() {
+            let server = server_lock.read().await;
+            if !Self::is_eligible(&server, &healthy_servers, filter) {
+                continue;
+            }
+            if server.is_saturated() {
+                saturated += 1;
+                continue;
+            }
 
-            for server in servers.iter() {
-                let guard = server.read().await;
-                let connections = guard.connections.load(Ordering::Relaxed);
-                if connections < min_connections {
-                    min_connections = connections;
-                    min_server = server;
-                }
+            if filter.preferred.as_ref() == Some(&server.id) {
+                preferred = Some(candidates.len());
+            }
+            candidates.push(Candidate {
+                in_flight: server.in_flight(),
+                weight: server.weight,
+                priority: server.priority,
+                latency: server.latency(),
+            });
+            eligible.push(server_lock);
+        }
+
+        // Pick one of them with the routing strategy of the group, unless the preferred server
+        // is eligible and not outranked by a server of a higher priority
+        let top_priority = candidates.iter().map(|c| c.priority).max();
+        let preferred = preferred.filter(|idx| Some(candidates[*idx].priority) == top_priority);
+        let strategy = self.strategy().await;
+        let selected = preferred.or_else(|| strategy.select(&candidates, &self.cursor));
+        let server_lock = match selected {
+            Some(idx) => eligible[idx],
+            // the request may wait for a slot of a saturated server
+            None if saturated > 0 => return Err(self.saturated_error()),
+            None => {
+                let err_msg = format!("No healthy {} server matches the request", self.ty);
+                error!(target: "stdout", "{}", &err_msg);
+                return Err(ServerError::NotFoundServer(self.ty.to_string()));
             }
-            min_server
         };
 
         // Access the chosen server
-        let url = {
-            let server = server_lock.write().await;
-            server.connections.fetch_add(1, Ordering::Relaxed);
-            server.url.parse().unwrap()
+        let server = server_lock.read().await;
+        let url = server.url.parse::<Uri>().map_err(|e| {
+            let err_msg = format!("Invalid url of the server {}: {}", server.id, e);
+            error!(target: "stdout", "{}", &err_msg);
+            ServerError::Operation(err_msg)
+        })?;
+
+        // the server may have been saturated by a concurrent request in the meantime
+        let guard = match server.try_acquire() {
+            Some(guard) => guard.notify_on_release(self.queue.clone()),
+            None => return Err(self.saturated_error()),
+        };
+
+        // an open breaker whose cooldown is over lets this request through as a trial, which
+        // ends with the request even if no outcome is recorded
+        let trial = match server.breaker.on_dispatch() {
+            Dispatch::Pass => None,
+            Dispatch::Trial(trial) => {
+                dual_info!(
+                    "Circuit breaker of server {} is {}, dispatch a trial request",
+                    server.id,
+                    BreakerState::HalfOpen
+                );
+                Some(trial)
+            }
+            // a concurrent request took the trial in the meantime
+            Dispatch::Reject => return Err(self.saturated_error()),
         };
+        let guard = guard.with_trial(trial);
 
-        Ok(url)
+        Ok(TargetServer {
+            id: server.id.clone(),
+            url,
+            auth_headers: server.auth_headers(),
+            guard: Some(guard),
+            started: Instant::now(),
+            latency: server.latency.clone(),
+            breaker: server.breaker.clone(),
+        })
+    }
+}
+
+impl ServerGroup {
+    /// Returns whether a server allowed by the filter can take requests, possibly after waiting
+    /// for a free slot
+    pub(crate) async fn is_available(&self, filter: &ServerFilter) -> bool {
+        let servers = self.servers.read().await;
+        let healthy_servers = self.healthy_servers.read().await;
+        for server_lock in servers.iter() {
+            if Self::is_eligible(&*server_lock.read().await, &healthy_servers, filter) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Whether a server may be routed to: it is healthy, allowed by the filter, has no open
+    // circuit breaker and is not draining
+    fn is_eligible(
+        server: &Server,
+        healthy_servers: &HashSet<ServerId>,
+        filter: &ServerFilter,
+    ) -> bool {
+        healthy_servers.contains(&server.id)
+            && filter.allows(server)
+            && server.breaker.is_available()
+            && !server.is_draining()
+    }
+
+    // Error returned if every server matching the request is saturated
+    fn saturated_error(&self) -> ServerError {
+        ServerError::TooManyRequests {
+            message: format!("All {} servers matching the request are busy", self.ty),
+            retry_after: 1,
+        }
     }
 }
 
 #[async_trait]
 pub(crate) trait RoutingPolicy: Sync + Send {
-    async fn next(&self) -> Result<Uri, ServerError>;
+    /// Picks a server and reserves an in-flight slot on it until the returned target is dropped
+    async fn next(&self, filter: &ServerFilter) -> Result<TargetServer, ServerError>;
+}
+
+/// Query parameters of the admin endpoint that registers a server
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct RegisterOptions {
+    /// Replace a server already registered with the same url instead of rejecting the request
+    #[serde(default)]
+    pub(crate) replace: bool,
+}
+
+/// Query parameters of the admin endpoint that lists the registered servers
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct ListOptions {
+    /// Label selector the listed servers must match, e.g. `tier=premium`
+    #[serde(default)]
+    pub(crate) selector: Option<String>,
+}
+
+/// Request body of the admin endpoint that updates a registered server
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct ServerUpdate {
+    pub(crate) url: Option<String>,
+    pub(crate) kind: Option<ServerKind>,
+    pub(crate) weight: Option<u32>,
+    pub(crate) priority: Option<i32>,
+    /// `null` lifts the limit of the server
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub(crate) max_concurrency: Option<Option<usize>>,
+    /// Replaces all labels of the server
+    pub(crate) labels: Option<BTreeMap<String, String>>,
+}
+impl ServerUpdate {
+    /// Applies the update to a server
+    pub(crate) fn apply(&self, server: &mut Server) {
+        if let Some(url) = &self.url {
+            server.url = url.clone();
+        }
+        if let Some(kind) = self.kind {
+            server.kind = kind;
+        }
+        if let Some(weight) = self.weight {
+            server.weight = weight;
+        }
+        if let Some(priority) = self.priority {
+            server.priority = priority;
+        }
+        if let Some(max_concurrency) = self.max_concurrency {
+            server.max_concurrency = max_concurrency;
+        }
+        if let Some(labels) = &self.labels {
+            server.labels = labels.clone();
+        }
+    }
+}
+
+// Deserialize a field that is present, so that an explicit `null` can be told apart from a
+// missing field, which is `None` by `#[serde(default)]`
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Restricts the servers a routing policy may choose from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ServerFilter {
+    /// If set, only the servers with these ids are eligible
+    pub(crate) server_ids: Option<HashSet<ServerId>>,
+    /// Servers that must not be chosen, e.g. because a request already failed on them
+    pub(crate) excluded: HashSet<ServerId>,
+    /// If set, only the servers whose labels match the selector are eligible
+    pub(crate) selector: Option<LabelSelector>,
+    /// Server picked over the routing strategy if it is eligible and in the highest priority tier
+    pub(crate) preferred: Option<ServerId>,
+}
+impl ServerFilter {
+    pub(crate) fn with_server_ids(server_ids: HashSet<ServerId>) -> Self {
+        Self {
+            server_ids: Some(server_ids),
+            ..Default::default()
+        }
+    }
+
+    /// Excludes a server from the eligible servers
+    pub(crate) fn exclude(&mut self, server_id: impl Into<ServerId>) {
+        self.excluded.insert(server_id.into());
+    }
+
+    /// Prefers a server over the routing strategy, e.g. the server a session is pinned to
+    pub(crate) fn prefer(mut self, server_id: Option<ServerId>) -> Self {
+        self.preferred = server_id;
+        self
+    }
+
+    /// Restricts the eligible servers to the ones matching the label selector
+    pub(crate) fn with_selector(mut self, selector: Option<LabelSelector>) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    pub(crate) fn allows(&self, server: &Server) -> bool {
+        if self.excluded.contains(&server.id) {
+            return false;
+        }
+
+        if let Some(selector) = &self.selector {
+            if !selector.matches(&server.labels) {
+                return false;
+            }
+        }
+
+        match &self.server_ids {
+            Some(server_ids) => server_ids.contains(&server.id),
+            None => true,
+        }
+    }
+}
+
+/// Selects the servers whose labels match all of the given `key=value` pairs, e.g.
+/// `tier=premium,region=eu`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LabelSelector(BTreeMap<String, String>);
+impl LabelSelector {
+    pub(crate) fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.0
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+impl std::str::FromStr for LabelSelector {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels = BTreeMap::new();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            match pair.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => {
+                    labels.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                _ => {
+                    return Err(ServerError::BadRequest(format!(
+                    "Invalid label selector `{}`, expected `key=value` pairs separated by commas",
+                    s
+                )))
+                }
+            }
+        }
+        Ok(Self(labels))
+    }
+}
+
+/// Checks that the labels can be matched by a label selector
+pub(crate) fn validate_labels(labels: &BTreeMap<String, String>) -> Result<(), String> {
+    for (key, value) in labels.iter() {
+        if key.trim().is_empty() || key.contains([',', '=']) || value.contains(',') {
+            return Err(format!("Invalid label of a server: {}={}", key, value));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_apply_server_update() {
+    let mut server = Server::new(
+        "chat-server-1".to_string(),
+        "http://localhost:8000",
+        ServerKind::chat,
+    );
+
+    let update: ServerUpdate =
+        serde_json::from_str(r#"{"kind": "chat,embeddings", "weight": 3}"#).unwrap();
+    update.apply(&mut server);
+
+    assert_eq!(server.id, "chat-server-1");
+    assert_eq!(server.url, "http://localhost:8000");
+    assert_eq!(server.kind, ServerKind::chat | ServerKind::embeddings);
+    assert_eq!(server.weight, 3);
+    assert!(server.labels.is_empty());
+
+    let update: ServerUpdate = serde_json::from_str(r#"{"labels": {"gpu": "a100"}}"#).unwrap();
+    update.apply(&mut server);
+    assert_eq!(server.labels["gpu"], "a100");
+    assert_eq!(server.weight, 3);
+
+    // a missing max_concurrency keeps the limit, `null` lifts it
+    let update: ServerUpdate = serde_json::from_str(r#"{"max_concurrency": 4}"#).unwrap();
+    update.apply(&mut server);
+    assert_eq!(server.max_concurrency, Some(4));
+    let update: ServerUpdate = serde_json::from_str(r#"{"priority": 1}"#).unwrap();
+    update.apply(&mut server);
+    assert_eq!(server.max_concurrency, Some(4));
+    let update: ServerUpdate = serde_json::from_str(r#"{"max_concurrency": null}"#).unwrap();
+    update.apply(&mut server);
+    assert_eq!(server.max_concurrency, None);
+}
+
+#[test]
+fn test_label_selector() {
+    let server: Server = serde_json::from_str(
+        r#"{"url": "http://localhost:8000", "kind": "chat", "labels": {"gpu": "a100", "tier": "premium"}}"#,
+    )
+    .unwrap();
+
+    let filter = ServerFilter::default().with_selector(Some("tier=premium".parse().unwrap()));
+    assert!(filter.allows(&server));
+    let filter =
+        ServerFilter::default().with_selector(Some(" tier = premium, gpu=a100 ".parse().unwrap()));
+    assert!(filter.allows(&server));
+    let filter =
+        ServerFilter::default().with_selector(Some("tier=premium,region=eu".parse().unwrap()));
+    assert!(!filter.allows(&server));
+
+    assert!("tier".parse::<LabelSelector>().is_err());
+    assert!("=premium".parse::<LabelSelector>().is_err());
+    assert!(serde_json::from_str::<Server>(
+        r#"{"url": "http://localhost:8000", "kind": "chat", "labels": {"a=b": "c"}}"#
+    )
+    .is_err());
 }